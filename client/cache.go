@@ -11,8 +11,29 @@ type CacheEntry struct {
 	offset uint32
 	data string
 	expiry int64
+	mtime uint64 // the server's modification time of the file when this data was read
 }
 
+// Checks if the requested range is a subset of the entry's range
+// offset must be [entry offset, entry offset+entry data length)
+// length must be [1, entry offset + entry data length - offset]
+func (entry CacheEntry) Covers(offset uint32, amount uint32) bool {
+	return offset >= entry.offset && offset < entry.offset + uint32(len(entry.data)) && amount <= entry.offset + uint32(len(entry.data)) - offset
+}
+
+// Returns the requested range from the entry's data (it is assumed that Covers is checked before calling this)
+func (entry CacheEntry) Slice(offset uint32, amount uint32) string {
+	return entry.data[offset-entry.offset:offset-entry.offset+amount]
+}
+
+// Result of looking up the cache
+type CacheResult int
+const (
+	Miss CacheResult = 0
+	Hit CacheResult = 1
+	Stale CacheResult = 2 // an expired entry was found which needs to be validated with the server
+)
+
 // The file name is instead used as a key for the cache map
 // Go equivalent of Map<string, CacheEntry[]>
 type CacheManager struct {
@@ -20,19 +41,27 @@ type CacheManager struct {
 }
 
 // Function to add a new entry to the cache map
-func (cm *CacheManager) AddEntry(filename string, offset uint32, data string) {
+func (cm *CacheManager) AddEntry(filename string, offset uint32, data string, mtime uint64) {
 	// Add the interval to the current time. This is when the cache entry expires
 	time_now := time.Now()
 	expiry := time_now.Add(time.Duration(t) * time.Millisecond).UnixMilli()
 	fmt.Printf("%s Adding cache entry of length %d for %s starting at offset %d. Valid until %d (%d + %d)\n", header(CacheHeader), len(data), filename, offset, expiry, time_now.UnixMilli(), t)
-	entry := CacheEntry{offset: offset, data: data, expiry: expiry}
+	entry := CacheEntry{offset: offset, data: data, expiry: expiry, mtime: mtime}
 
 	// Append to the CacheEntry array for this file
 	cm.cacheMap[filename] = append(cm.cacheMap[filename], entry)
 }
 
-// Function to check if the cache has a specific entry
-func (cm *CacheManager) GetEntry(filename string, offset uint32, amount uint32) (bool,string) {
+// Function to drop every entry of a file, used when it no longer exists
+func (cm *CacheManager) RemoveFile(filename string) {
+	fmt.Printf("%s Deleting all entries for %s\n", header(CacheHeader), filename)
+	delete(cm.cacheMap, filename)
+}
+
+/* Function to check if the cache has a specific entry
+   An expired entry that covers the requested range is removed and returned as stale,
+   so that the caller can validate it with the server and add it back */
+func (cm *CacheManager) GetEntry(filename string, offset uint32, amount uint32) (CacheResult, CacheEntry) {
 	fmt.Printf("%s Checking if an entry of size %d for %s starting at offset %d exists\n", header(CacheHeader), amount, filename, offset)
 	// Loop through all the entries belonging to the given file
 	if entries, ok := cm.cacheMap[filename]; ok {
@@ -42,19 +71,19 @@ func (cm *CacheManager) GetEntry(filename string, offset uint32, amount uint32)
 			time_now := time.Now().UnixMilli()
 			// Remove an entry if it has expired
 			if entry.expiry < time_now {
-				fmt.Printf("%s Deleting an old entry of size %d starting at offset %d. Entry was valid till %d but the time now is %d\n", header(CacheHeader), len(entry.data), entry.offset, entry.expiry, time_now)
 				cm.cacheMap[filename] = slices.Delete(cm.cacheMap[filename], i, i+1)
-			} else {
-				// If the entry is valid then we need to check if requested range is a subset of the entry's range
-				// offset must be [entry offset, entry offset+entry data length)
-				// length must be [1, entry offset + entry data length - offset]
-				if offset >= entry.offset && offset < entry.offset + uint32(len(entry.data)) && amount <= entry.offset + uint32(len(entry.data)) - offset {
-					fmt.Printf("%s Found a valid entry set to expire at %d. Time now is %d\n", header(CacheHeader), entry.expiry, time_now)
-					return true, entry.data[offset-entry.offset:offset-entry.offset+amount]
+				if entry.Covers(offset, amount) {
+					fmt.Printf("%s Found an expired entry last modified at %d. Entry was valid till %d but the time now is %d\n", header(CacheHeader), entry.mtime, entry.expiry, time_now)
+					return Stale, entry
 				}
+				fmt.Printf("%s Deleting an old entry of size %d starting at offset %d. Entry was valid till %d but the time now is %d\n", header(CacheHeader), len(entry.data), entry.offset, entry.expiry, time_now)
+			} else if entry.Covers(offset, amount) {
+				// If the entry is valid then we need to check if requested range is a subset of the entry's range
+				fmt.Printf("%s Found a valid entry set to expire at %d. Time now is %d\n", header(CacheHeader), entry.expiry, time_now)
+				return Hit, entry
 			}
 		}
 	}
 	fmt.Printf("%s Could not find any valid entry\n", header(CacheHeader))
-	return false, ""
-}
\ No newline at end of file
+	return Miss, CacheEntry{}
+}
@@ -1,6 +1,7 @@
 package main
 
 import (
+	"encoding/binary"
 	"fmt"
 	"net"
 	"os"
@@ -78,18 +79,128 @@ func DisconnectFromServer() {
 	exit()
 }
 
-// Function used to wait for monitor updates and process them
-func WaitForUpdates(wait_until time.Time, path string) {
+/* A fragment has a 13 byte header: the Fragment status, the message id,
+   the sequence number of the fragment and the total number of fragments */
+const fragmentHeaderSize = 13
+
+// Asks the server to send the fragments of a message that haven't been received yet
+func RequestMissing(id uint32, total uint32, fragments map[uint32][]byte) {
+	req := Request(Resend)
+	req.AddInt(id)
+	req.AddInt(total - uint32(len(fragments)))
+	for seq := uint32(0); seq < total; seq++ {
+		if _, ok := fragments[seq]; !ok {
+			req.AddInt(seq)
+		}
+	}
+	fmt.Printf("%s Missing %d of %d fragments of message %d\n", header(ClientHeader), total - uint32(len(fragments)), total, id)
+	conn.Write(req.buf)
+}
+
+/* Reads a single message from the server until the deadline, reassembling it if it was split into fragments.
+   Once the first fragment arrives, missing fragments are asked for again after every timeout (up to the number of retries) */
+func Receive(deadline time.Time) ([]byte, error) {
+	buf := make([]byte, 1024 * 1024)
+	conn.SetReadDeadline(deadline)
+	amt, err := conn.Read(buf)
+	if err != nil {
+		return nil, err
+	}
+	if Status(buf[0]) != Fragment || amt < fragmentHeaderSize {
+		return buf[:amt], nil
+	}
+
+	// keep reading until every fragment of this message has been received
+	id := binary.BigEndian.Uint32(buf[1:5])
+	total := binary.BigEndian.Uint32(buf[9:13])
+	fragments := make(map[uint32][]byte)
+	attempts := retries
+	for {
+		fmt.Printf("%s Received %d bytes\n", header(UDPHeader), amt)
+		// fragments of any other message are dropped
+		if Status(buf[0]) == Fragment && amt >= fragmentHeaderSize && binary.BigEndian.Uint32(buf[1:5]) == id {
+			seq := binary.BigEndian.Uint32(buf[5:9])
+			fragments[seq] = append([]byte(nil), buf[fragmentHeaderSize:amt]...)
+		}
+		if uint32(len(fragments)) >= total {
+			break
+		}
+
+		conn.SetReadDeadline(time.Now().Add(time.Duration(timeout) * time.Millisecond))
+		amt, err = conn.Read(buf)
+		for err != nil {
+			if netErr, ok := err.(net.Error); !ok || !netErr.Timeout() || attempts == 0 {
+				return nil, err
+			}
+			attempts = CheckAttempts(attempts)
+			RequestMissing(id, total, fragments)
+			conn.SetReadDeadline(time.Now().Add(time.Duration(timeout) * time.Millisecond))
+			amt, err = conn.Read(buf)
+		}
+	}
+
+	// put the fragments back together in order
+	message := make([]byte, 0)
+	for seq := uint32(0); seq < total; seq++ {
+		message = append(message, fragments[seq]...)
+	}
+	fmt.Printf("%s Reassembled %d fragments into %d bytes\n", header(UDPHeader), total, len(message))
+	return message, nil
+}
+
+// the sequence number of the latest update received for each monitor
+var monitorSeqs = make(map[uint32]uint32)
+
+// while waiting for monitor updates, those that arrive in the middle of a request are kept here to be handled afterwards
+var monitoring = false
+var queuedUpdates [][]byte
+
+// Tells the server that a monitor update has arrived so that it isn't sent again, which has no response
+func AcknowledgeUpdate(id uint32, seq uint32) {
+	req := Request(MonitorAck)
+	req.AddInt(id)
+	req.AddInt(seq)
+	conn.Write(req.buf)
+}
+
+/* Asks the server for the whole file a monitor is on, after an update has been missed
+   the content is preceded by the sequence number of the latest update, which is moved into seq */
+func ResyncMonitor(id uint32) (bool, ResponseHandler) {
+	req := Request(Resync)
+	req.AddInt(id)
+	req.PrintNumber()
+	fmt.Printf("%s Sending request to resync monitor %d\n", header(ClientHeader), id)
+	success, response := Send(req)
+	if success && len(response.data) >= 4 {
+		response.seq = binary.BigEndian.Uint32([]byte(response.data[:4]))
+		response.data = response.data[4:]
+	}
+	return success, response
+}
+
+/* Function used to wait for monitor updates and process them
+   updates are only cached when they are the whole file, rather than a range of it */
+func WaitForUpdates(wait_until time.Time, path string, whole bool) {
 	fmt.Printf("%s Entering Monitor Mode\n", header(MonitorHeader))
+	monitoring = true
+	defer func() {
+		// updates that are still queued belong to a monitor that is over
+		monitoring = false
+		queuedUpdates = nil
+	}()
 	for { // while(True)
-		buf := make([]byte, 1024 * 1024)
-
 		/* wait_until is the monitor expiry time
-		   setReadDeadline will throw a timeout error if it's waiting for
+		   the read will throw a timeout error if it's waiting for
 		   some data and the expiry time passes */
-		conn.SetReadDeadline(wait_until) 
-		
-		amt, err := conn.Read(buf)
+		var buf []byte
+		var err error
+		if len(queuedUpdates) > 0 {
+			// updates that arrived while waiting for a response are handled first
+			buf, queuedUpdates = queuedUpdates[0], queuedUpdates[1:]
+		} else {
+			buf, err = Receive(wait_until)
+		}
+		amt := len(buf)
 		if err != nil {
 			// timeout error indicates monitor interval has passed which means we need to exit monitor mode
 			if netErr, ok := err.(net.Error); ok && netErr.Timeout() {
@@ -103,21 +214,53 @@ func WaitForUpdates(wait_until time.Time, path string) {
 			// process data that is received
 			fmt.Printf("%s Received %d bytes\n", header(UDPHeader), amt)
 
-			// wrap received data to a Response and print it out
-			response := Response(buf, amt)
+			// anything other than an update is a late response to an earlier request
+			if !IsNotification(buf) {
+				continue
+			}
+
+			// unwrap the update and acknowledge it, even if it was received before
+			id, response := Notification(buf)
+			AcknowledgeUpdate(id, response.seq)
+			last := monitorSeqs[id]
+			if response.seq <= last {
+				fmt.Printf("%s Update %d was already received\n", header(MonitorHeader), response.seq)
+				continue
+			}
+			// a gap in the sequence numbers means an update was missed, so the whole file is asked for again
+			if response.seq != last + 1 && response.Ok() {
+				fmt.Printf("%s Missed updates %d to %d! Resyncing\n", header(MonitorHeader), last + 1, response.seq - 1)
+				if success, resynced := ResyncMonitor(id); success && resynced.seq >= response.seq {
+					response = resynced
+				}
+			}
+			monitorSeqs[id] = response.seq
+			// a directory is being monitored, so the update is about one of its files
+			if response.status == Entry {
+				event, name := EntryChange(response.data)
+				fmt.Printf("%s %s was %s\n", header(MonitorHeader), name, event)
+				continue
+			}
 			col := header(ServerGood)
-			if response.status == Bad {
+			if !response.Ok() {
 				col = header(ServerBad)
 			} else {
 				fmt.Printf("%s File Changed!\n",  header(MonitorHeader))
 				/* we add this data to the cache
 				   but we only do this when the time to expiry is less than the freshness interval
 				   otherwise it's a waste */
-				if time.Until(wait_until).Milliseconds() < int64(t) {
-					cache_manager.AddEntry(path, 0, response.data)
+				if whole && time.Until(wait_until).Milliseconds() < int64(t) {
+					cache_manager.AddEntry(path, 0, response.data, response.timestamp)
 				}
 			}
 			fmt.Printf("%s %s\n", col, response.data)
+
+			// the server drops all monitors of a removed file, so there is nothing left to wait for
+			if response.status == NotFound {
+				cache_manager.RemoveFile(path)
+				fmt.Printf("%s File was removed! Exiting Monitor Mode\n", header(MonitorHeader))
+				break
+			}
 		}
 	}
 }
@@ -134,8 +277,11 @@ func CheckAttempts(attempts int) int {
 	return attempts
 }
 
-// All requests are sent via this function
-func Send(req *RequestMarshal) (bool, string) {
+/* All requests are sent via this function
+   success is true when the server replied with either a good or unchanged status */
+func Send(req *RequestMarshal) (bool, ResponseHandler) {
+	// whether it succeeds or not, this request won't be sent again once this function returns
+	defer func() { ackNo = req.number + 1 }()
 	attempts := retries
 	for attempts > 0 { 
 		_, err := conn.Write(req.buf)
@@ -150,9 +296,19 @@ func Send(req *RequestMarshal) (bool, string) {
 		}
 
 		// wait for a response till timeout if no write error
-		p :=  make([]byte, 1024 * 1024)
-		conn.SetReadDeadline(time.Now().Add(time.Duration(timeout) * time.Millisecond))
-		amt, err := conn.Read(p)
+		deadline := time.Now().Add(time.Duration(timeout) * time.Millisecond)
+		p, err := Receive(deadline)
+		/* monitor updates can arrive in the middle of a request, even after the monitor has ended as the server keeps sending them until they are acknowledged
+		   they are acknowledged either way, and kept for later while waiting for updates */
+		for err == nil && IsNotification(p) {
+			id, update := Notification(p)
+			AcknowledgeUpdate(id, update.seq)
+			if monitoring {
+				queuedUpdates = append(queuedUpdates, p)
+			}
+			p, err = Receive(deadline)
+		}
+		amt := len(p)
 		if err != nil {
 			// handle timeout errors separately as this could indicate message loss
 			if netErr, ok := err.(net.Error); ok && netErr.Timeout() {
@@ -166,14 +322,15 @@ func Send(req *RequestMarshal) (bool, string) {
 		} else {
 			// wrap received data to a Response and print it out
 			fmt.Printf("%s Received %d bytes\n", header(UDPHeader), amt)
-			response := Response(p, amt)
-			col := header(ServerGood)
-			if response.status == Bad {
-				col = header(ServerBad)
+			response := Response(p, amt, req.HasTimestamp())
+			if response.Ok() {
+				fmt.Printf("%s %s\n", header(ServerGood), response.data)
+			} else {
+				// errors are printed along with their status so that they can be told apart
+				fmt.Printf("%s %s: %s\n", header(ServerBad), response.status, response.data)
 			}
-			fmt.Printf("%s %s\n", col, response.data)
-			return response.status == Good, response.data
+			return response.Ok(), response
 		}
 	}
-	return false, ""
+	return false, ResponseHandler{status: Bad}
 }
\ No newline at end of file
@@ -1,12 +1,17 @@
 package main
 
-import "fmt"
+import (
+	"encoding/binary"
+	"fmt"
+)
 
 // used to keep track of the request number internally
 // this will be sent to the server as well
 // each session starts with a request number of 0
+// ackNo is sent along with it to tell the server that every request numbered below it is done with
 var (
 	reqNo uint32 = 0
+	ackNo uint32 = 0
 )
 
 // Operation constants for easy access and modification if need be
@@ -19,18 +24,32 @@ const (
 	Update Operation = 4
 	Delete Operation = 5
 	Monitor Operation = 6
+	Validate Operation = 7
+	Create Operation = 8
+	Remove Operation = 9
+	List Operation = 10
+	Stat Operation = 11
+	Append Operation = 12
+	Resend Operation = 13
+	Unmonitor Operation = 14
+	ListMonitors Operation = 15
+	MonitorAck Operation = 16
+	Resync Operation = 17
 )
 
 // A container for the request buffer to build functions on top of it
 type RequestMarshal struct {
 	buf []uint8
+	op Operation
+	number uint32
 }
 
 /* Creates a new request container.
-   Always adds the operation as a the first byte and then a 4-byte request number */
+   Always adds a 4-byte request number, a 4-byte acknowledgement and then the operation as a byte */
 func Request(op Operation) *RequestMarshal {
-	c := &RequestMarshal{buf: make([]uint8, 0)}
+	c := &RequestMarshal{buf: make([]uint8, 0), op: op, number: reqNo}
 	c.AddInt(reqNo)
+	c.AddInt(ackNo)
 	c.buf = append(c.buf, uint8(op))
 	// increment the request number for the next request
 	reqNo++
@@ -43,15 +62,30 @@ func (c *RequestMarshal) AddInt(val uint32) {
 	c.buf = append(c.buf, uint8((val >> 24) & 0xFF), uint8((val >> 16) & 0xFF), uint8((val >> 8) & 0xFF), uint8(val & 0xFF))
 }
 
+// Marshalls a long integer into 8 bytes, used for timestamps
+func (c *RequestMarshal) AddLong(val uint64) {
+	c.AddInt(uint32(val >> 32))
+	c.AddInt(uint32(val & 0xFFFFFFFF))
+}
+
+// Marshalls a single byte, used for flags
+func (c *RequestMarshal) AddByte(val byte) {
+	c.buf = append(c.buf, val)
+}
+
 // Marshalls a string into 4 + n bytes
 func (c *RequestMarshal) AddString(val string) {
+	c.AddBytes([]byte(val))
+}
+
+// Marshalls raw bytes into 4 + n bytes
+func (c *RequestMarshal) AddBytes(val []byte) {
 	// Add the length as an unsigned integer first
 	c.AddInt(uint32(len(val)))
 
-	// Add all the bytes of the string
-	for _, char := range val {
-        c.buf = append(c.buf, uint8(char))
-    }
+	/* Add all the bytes as they are. Ranging over a string would give runes instead,
+	   which truncates multi-byte characters and binary data */
+	c.buf = append(c.buf, val...)
 }
 
 /* A function to print the request number. 
@@ -74,21 +108,130 @@ func (c *RequestMarshal) AddStringInput(prompt string) string {
 	return val
 }
 
-// Status type as received from the server
+/* Status type as received from the server
+   Every status other than Good and Unchanged is an error, which comes with an optional message */
 type Status int
 const (
 	Bad Status = 0
 	Good Status = 1
+	Unchanged Status = 2
+	OutsideRoot Status = 3
+	Malformed Status = 4
+	NotFound Status = 5
+	OutOfRange Status = 6
+	PermissionDenied Status = 7
+	IOError Status = 8
+	Unsupported Status = 9
+	AlreadyExists Status = 10
+	Fragment Status = 11 // marks a datagram as one piece of a larger response
+	Notify Status = 12 // marks an update pushed to a client monitoring a file
+	Delta Status = 13 // marks an update that describes a change, which is only sent to monitors that ask for them
+	Entry Status = 14 // marks an update about a file in a monitored directory
+	NotADirectory Status = 15
+	Stale Status = 16 // the request was too old for the server to tell whether it had already been carried out
 )
 
+// Readable names of each status, used when printing errors
+func (s Status) String() string {
+	switch s {
+	case Good:
+		return "GOOD"
+	case Unchanged:
+		return "UNCHANGED"
+	case OutsideRoot:
+		return "OUTSIDE_ROOT"
+	case Malformed:
+		return "MALFORMED"
+	case NotFound:
+		return "NOT_FOUND"
+	case OutOfRange:
+		return "OUT_OF_RANGE"
+	case PermissionDenied:
+		return "PERMISSION_DENIED"
+	case IOError:
+		return "IO_ERROR"
+	case Unsupported:
+		return "UNSUPPORTED"
+	case AlreadyExists:
+		return "ALREADY_EXISTS"
+	case Fragment:
+		return "FRAGMENT"
+	case Notify:
+		return "NOTIFY"
+	case Delta:
+		return "DELTA"
+	case Entry:
+		return "ENTRY"
+	case NotADirectory:
+		return "NOT_A_DIRECTORY"
+	case Stale:
+		return "STALE"
+	}
+	return "BAD"
+}
+
 // A container for the response given by a server
 type ResponseHandler struct {
 	status Status
+	timestamp uint64
 	data string
+	seq uint32 // only set for monitor updates
 }
 
 /* Divides the data received from the server into a 1-byte status
-   and an n-1 byte string wrapping it into the ResponseHandler container */
-func Response(raw []byte, amt int) ResponseHandler {
-	return ResponseHandler{status: Status(raw[0]), data: string(raw[1:amt])}
+   and an n-1 byte string wrapping it into the ResponseHandler container.
+   File content is preceded by an 8-byte modification time, which is only
+   there if the response was successful */
+func Response(raw []byte, amt int, hasTimestamp bool) ResponseHandler {
+	response := ResponseHandler{status: Status(raw[0]), data: string(raw[1:amt])}
+	if hasTimestamp && response.Ok() && amt >= 9 {
+		for _, b := range raw[1:9] {
+			response.timestamp = (response.timestamp << 8) | uint64(b)
+		}
+		response.data = string(raw[9:amt])
+	}
+	return response
+}
+
+/* A monitor update has a 9 byte header: the Notify status, the id of the monitor
+   and a sequence number, which goes up by one with every update of that monitor */
+const notificationHeaderSize = 9
+
+// Checks whether a message is a monitor update rather than a response
+func IsNotification(raw []byte) bool {
+	return len(raw) > notificationHeaderSize && Status(raw[0]) == Notify
+}
+
+// Unwraps a monitor update, returning the id of its monitor and the update itself
+func Notification(raw []byte) (uint32, ResponseHandler) {
+	id := binary.BigEndian.Uint32(raw[1:5])
+	response := Response(raw[notificationHeaderSize:], len(raw) - notificationHeaderSize, true)
+	response.seq = binary.BigEndian.Uint32(raw[5:9])
+	return id, response
+}
+
+/* Unwraps an update about a file in a monitored directory, returning what happened to it and its path from the directory
+   it is the event as a byte followed by the length-prefixed path */
+func EntryChange(data string) (string, string) {
+	if len(data) < 5 {
+		return "changed", data
+	}
+	event := "changed"
+	switch data[0] {
+	case 0:
+		event = "created"
+	case 2:
+		event = "removed"
+	}
+	return event, data[5:]
+}
+
+// Any other status means the server couldn't carry out the request
+func (r ResponseHandler) Ok() bool {
+	return r.status == Good || r.status == Unchanged
+}
+
+// Only reads, validations and resyncs have file content along with a timestamp in their response
+func (c *RequestMarshal) HasTimestamp() bool {
+	return c.op == Read || c.op == Validate || c.op == Resync
 }
\ No newline at end of file
@@ -15,14 +15,34 @@ func ReadService() {
 	// Read all the parameters from the console first
 	path := readString("File Path: ")
 	offset := readInt("Offset: ", 0)
-	amount := readInt("Amount: ", 1)
+	// reading 0 bytes is allowed, which is how an empty file can be read
+	amount := readInt("Amount: ", 0)
 	fmt.Println()
 
 	// Check if the cache can service this request 
-	has_entry, entry := cache_manager.GetEntry(path, offset, amount)
-	if has_entry {
+	result, entry := cache_manager.GetEntry(path, offset, amount)
+	if result == Hit {
 		// Return the data from cache
-		fmt.Printf("%s %s\n", header(ClientHeader), entry)
+		fmt.Printf("%s %s\n", header(ClientHeader), entry.Slice(offset, amount))
+	} else if result == Stale {
+		// Ask the server if the file has been modified since the expired entry was read
+		req := Request(Validate)
+		req.AddString(path)
+		req.AddInt(offset)
+		req.AddInt(amount)
+		req.AddLong(entry.mtime)
+
+		req.PrintNumber()
+		fmt.Printf("%s Sending request to validate %d bytes starting at offset %d in %s last modified at %d\n", header(ClientHeader), amount, offset, path, entry.mtime)
+		success, response := Send(req)
+		if response.status == Unchanged {
+			// the file hasn't changed so the whole entry is added back with a new expiry
+			cache_manager.AddEntry(path, entry.offset, entry.data, entry.mtime)
+			fmt.Printf("%s %s\n", header(ClientHeader), entry.Slice(offset, amount))
+		} else if success {
+			// the server sent back fresh data which replaces the expired entry
+			cache_manager.AddEntry(path, offset, response.data, response.timestamp)
+		}
 	} else {
 		// Initialize and send a request to the server
 		req := Request(Read)
@@ -32,10 +52,10 @@ func ReadService() {
 		
 		req.PrintNumber()
 		fmt.Printf("%s Sending request to read %d bytes starting at offset %d in %s\n", header(ClientHeader), amount, offset, path)
-		success, data := Send(req)
+		success, response := Send(req)
 		if success {
 			// save the data received as a new entry in the cache if it was successful
-			cache_manager.AddEntry(path, offset, data)
+			cache_manager.AddEntry(path, offset, response.data, response.timestamp)
 		}
 	}
 }
@@ -88,15 +108,29 @@ func MonitorService() {
 	req := Request(Monitor)
 	path := req.AddStringInput("File Path: ")
 	interval := req.AddIntInput("Interval (ms): ", 1)
+	// only part of the file can be monitored, which starts at an offset
+	length := readInt("Length (0 for the whole file): ", 0)
+	var offset uint32
+	if length > 0 {
+		offset = readInt("Offset: ", 0)
+		// the whole range is sent with every update, so no flags are set before it
+		req.AddByte(0)
+		req.AddInt(offset)
+		req.AddInt(length)
+	}
 	
 	fmt.Println()
 	req.PrintNumber()
-	fmt.Printf("%s Sending request to monitor any updates to %s for the next %dms\n", header(ClientHeader), path, interval)
+	if length > 0 {
+		fmt.Printf("%s Sending request to monitor any updates to %d bytes starting at offset %d in %s for the next %dms\n", header(ClientHeader), length, offset, path, interval)
+	} else {
+		fmt.Printf("%s Sending request to monitor any updates to %s for the next %dms\n", header(ClientHeader), path, interval)
+	}
 	success, _ := Send(req)
 	
 	if success {
 		// start monitoring changes until the interval is passed if it was successful
 		monitor_end := time.Now().Add(time.Duration(interval) * time.Millisecond)
-		WaitForUpdates(monitor_end, path)
+		WaitForUpdates(monitor_end, path, length == 0)
 	}
 }
@@ -0,0 +1,584 @@
+/* A client for the remote file server, speaking the same protocol as the Go client over UDP
+   Request numbering, acknowledgements, the session id, timeouts, retries and the reassembly of fragmented responses
+   are all taken care of here, so every operation is a single method call. The wire format comes from the protocol crate,
+   which the server uses as well */
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use protocol::{CreateFlag, DecodeError, EntryChange, EntryEvent, EntryType, MonitorFlag, RequestOperation, StatusCode};
+use protocol::{Delta, Fragment, Notification, Reader, RequestMarshal, ResponseMarshal};
+
+// Reasons why an operation could not be carried out
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error), // the socket itself failed
+    Timeout, // no response was received after every retry
+    Malformed(DecodeError), // the server sent back something that couldn't be unmarshalled
+    Status { status: u8, message: String } // the server replied with an error status and its readable message
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "{err}"),
+            ClientError::Timeout => write!(f, "Response Timed Out"),
+            ClientError::Malformed(err) => write!(f, "Malformed response: {err}"),
+            ClientError::Status { status, message } => write!(f, "{}: {message}", StatusCode::name(*status)),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        return ClientError::Io(err);
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> ClientError {
+        return ClientError::Malformed(err);
+    }
+}
+
+// File content along with the modification time it corresponds to
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub data: Vec<u8>,
+    pub modified: u64 // epoch milliseconds
+}
+
+// An entry of a directory listing
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub entry_type: u8, // one of the EntryType constants
+    pub size: u64,
+    pub modified: u64 // epoch milliseconds
+}
+
+// What the server knows about a file or directory
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub entry_type: u8, // one of the EntryType constants
+    pub size: u64,
+    pub modified: u64, // epoch milliseconds
+    pub permissions: u32 // unix permission bits
+}
+
+// A monitor the client has on a file
+#[derive(Debug, Clone)]
+pub struct MonitorInfo {
+    pub path: String, // the path as it was given when the monitor was started
+    pub remaining: Duration // how long until the monitor expires
+}
+
+// An update pushed by the server to a client monitoring a file
+#[derive(Debug, Clone)]
+pub enum MonitorEvent {
+    Changed(FileContent), // the new content of the whole file
+    Removed, // the file is gone, along with every monitor on it
+    Entry(EntryChange) // a file in a monitored directory has been created, changed or removed
+}
+
+/* ClientBuilder collects the configuration of a client before it connects
+   the defaults are the same as those of the Go client, except that any free local port is used */
+pub struct ClientBuilder {
+    bind: String,
+    timeout: Duration,
+    retries: u32,
+    session_id: Option<u32>
+}
+
+impl Default for ClientBuilder {
+    fn default() -> ClientBuilder {
+        return ClientBuilder{bind: String::from("0.0.0.0:0"), timeout: Duration::from_millis(3000), retries: 3, session_id: None};
+    }
+}
+
+impl ClientBuilder {
+    // the local address the client sends from
+    pub fn bind(mut self, address: impl Into<String>) -> ClientBuilder {
+        self.bind = address.into();
+        return self;
+    }
+
+    // how long to wait for a response before sending the request again
+    pub fn timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.timeout = timeout;
+        return self;
+    }
+
+    // the number of times a request is sent before giving up, which is also how often missing fragments are asked for
+    pub fn retries(mut self, retries: u32) -> ClientBuilder {
+        self.retries = retries.max(1);
+        return self;
+    }
+
+    // the session id sent with the handshake, which is the current time in seconds unless given
+    pub fn session_id(mut self, session_id: u32) -> ClientBuilder {
+        self.session_id = Some(session_id);
+        return self;
+    }
+
+    // binds the socket and handshakes with the server, which starts a new session
+    pub fn connect(self, server: impl ToSocketAddrs) -> Result<Client, ClientError> {
+        let socket = UdpSocket::bind(&self.bind)?;
+        socket.connect(server)?;
+        // epoch time in seconds being stored in an unsigned integer will work till 2106, which should be good enough
+        let session_id = self.session_id.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32);
+        let mut client = Client{socket, session_id, req_no: 0, ack: 0, timeout: self.timeout, retries: self.retries, monitor_seqs: HashMap::new(), monitor_copies: HashMap::new(), monitoring: false, queued: VecDeque::new()};
+        client.handshake()?;
+        return Ok(client);
+    }
+}
+
+// Client is a session with the server, every method sends a single request and waits for its response
+pub struct Client {
+    socket: UdpSocket,
+    session_id: u32,
+    req_no: u32, // the number of the next request, each session starts at 0
+    ack: u32, // sent along with every request to tell the server that every request numbered below it is done with
+    timeout: Duration,
+    retries: u32,
+    monitor_seqs: HashMap<u32, u32>, // the sequence number of the latest update received for each monitor
+    monitor_copies: HashMap<u32, Vec<u8>>, // the content of the file each monitor is on, which deltas are applied to
+    monitoring: bool, // whether monitor updates are being waited for, in which case updates that arrive in the middle of a request are kept
+    queued: VecDeque<Notification> // updates that arrived while waiting for a response, which have been acknowledged already
+}
+
+impl Client {
+    pub fn builder() -> ClientBuilder {
+        return ClientBuilder::default();
+    }
+
+    // connects to a server with the default configuration
+    pub fn connect(server: impl ToSocketAddrs) -> Result<Client, ClientError> {
+        return Client::builder().connect(server);
+    }
+
+    pub fn session_id(&self) -> u32 {
+        return self.session_id;
+    }
+
+    // starts a new request, which takes up the next request number
+    fn request(&mut self, op: u8) -> RequestMarshal {
+        let req = RequestMarshal::new(self.req_no, self.ack, op);
+        self.req_no = self.req_no.wrapping_add(1);
+        return req;
+    }
+
+    // starts a new request on a file, since every service has the file path first
+    fn file_request(&mut self, op: u8, path: &str) -> RequestMarshal {
+        let mut req = self.request(op);
+        req.add_bytes(path.as_bytes());
+        return req;
+    }
+
+    // waits for a single datagram until the deadline, returning None once it has passed
+    fn receive_datagram(&self, buf: &mut [u8], deadline: Instant) -> Result<Option<usize>, ClientError> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        self.socket.set_read_timeout(Some(deadline - now))?;
+        match self.socket.recv(buf) {
+            Ok(amt) => return Ok(Some(amt)),
+            Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => return Ok(None),
+            // nothing is listening at the server address (yet), which is retried just like a lost response
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+            Err(err) => return Err(ClientError::Io(err)),
+        }
+    }
+
+    // asks the server to send the fragments of a message that haven't been received yet
+    fn request_missing(&mut self, id: u32, total: u32, fragments: &BTreeMap<u32, Vec<u8>>) -> Result<(), ClientError> {
+        let mut req = self.request(RequestOperation::RESEND);
+        req.add_int(id);
+        req.add_int(total - fragments.len() as u32);
+        for seq in 0..total {
+            if !fragments.contains_key(&seq) {
+                req.add_int(seq);
+            }
+        }
+        self.socket.send(req.to_bytes())?;
+        return Ok(());
+    }
+
+    /* reads a single message from the server until the deadline, reassembling it if it was split into fragments.
+       Once the first fragment arrives, missing fragments are asked for again after every timeout (up to the number of retries) */
+    fn receive(&mut self, deadline: Instant) -> Result<Option<Vec<u8>>, ClientError> {
+        let mut buf: Vec<u8> = vec![0; 65536];
+        let amt = match self.receive_datagram(&mut buf, deadline)? {
+            Some(amt) => amt,
+            None => return Ok(None),
+        };
+        let (id, total) = match Fragment::from_bytes(&buf[..amt]) {
+            Some(fragment) => (fragment.id, fragment.total),
+            None => return Ok(Some(buf[..amt].to_vec())),
+        };
+
+        // keep reading until every fragment of this message has been received
+        let mut fragments: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        let mut attempts = self.retries;
+        let mut received = Some(amt);
+        loop {
+            if let Some(amt) = received {
+                // fragments of any other message are dropped
+                if let Some(fragment) = Fragment::from_bytes(&buf[..amt]).filter(|fragment| fragment.id == id && fragment.seq < total) {
+                    fragments.insert(fragment.seq, fragment.chunk.to_vec());
+                }
+                if fragments.len() as u32 >= total {
+                    break;
+                }
+            } else {
+                if attempts == 0 {
+                    return Err(ClientError::Timeout);
+                }
+                attempts -= 1;
+                self.request_missing(id, total, &fragments)?;
+            }
+            received = self.receive_datagram(&mut buf, Instant::now() + self.timeout)?;
+        }
+
+        // the map is ordered by sequence number, so the fragments can be put back together as they are
+        return Ok(Some(fragments.into_values().flatten().collect()));
+    }
+
+    /* all requests are sent via this function, which retries until a response arrives or the retries run out
+       any response is returned as is, whether its status is an error or not */
+    fn send(&mut self, req: RequestMarshal) -> Result<ResponseMarshal, ClientError> {
+        let buf = self.send_raw(&req)?;
+        return Ok(ResponseMarshal::from_bytes(&buf, req.has_timestamp())?);
+    }
+
+    // sends a request and returns the message that answers it, before it is unmarshalled
+    fn send_raw(&mut self, req: &RequestMarshal) -> Result<Vec<u8>, ClientError> {
+        let result = self.send_attempts(req);
+        // whether it succeeds or not, this request won't be sent again once this function returns
+        self.ack = req.number.wrapping_add(1);
+        return result;
+    }
+
+    fn send_attempts(&mut self, req: &RequestMarshal) -> Result<Vec<u8>, ClientError> {
+        for _ in 0..self.retries {
+            // a refusal reported for an earlier datagram is no reason to stop retrying
+            if let Err(err) = self.socket.send(req.to_bytes()) {
+                if err.kind() != ErrorKind::ConnectionRefused {
+                    return Err(ClientError::Io(err));
+                }
+            }
+            let deadline = Instant::now() + self.timeout;
+            while let Some(buf) = self.receive(deadline)? {
+                /* monitor updates can arrive in the middle of a request, even after the monitor has ended as the server keeps sending them until they are acknowledged
+                   they are acknowledged either way, and kept for later while updates are being waited for */
+                if Notification::is_notification(&buf) {
+                    let notification = Notification::from_bytes(&buf)?;
+                    self.acknowledge(&notification)?;
+                    if self.monitoring {
+                        self.queued.push_back(notification);
+                    }
+                    continue;
+                }
+                return Ok(buf);
+            }
+        }
+        return Err(ClientError::Timeout);
+    }
+
+    // tells the server that a monitor update has arrived, which has no response
+    fn acknowledge(&mut self, notification: &Notification) -> Result<(), ClientError> {
+        let mut req = self.request(RequestOperation::MONITOR_ACK);
+        req.add_int(notification.monitor_id);
+        req.add_int(notification.seq);
+        self.socket.send(req.to_bytes())?;
+        return Ok(());
+    }
+
+    /* asks the server for the whole file a monitor is on after an update has been missed
+       returns the sequence number of the latest update along with the content as of that update */
+    fn resync(&mut self, monitor_id: u32) -> Result<(u32, ResponseMarshal), ClientError> {
+        let mut req = self.request(RequestOperation::RESYNC);
+        req.add_int(monitor_id);
+        let mut response = self.call(req)?;
+        let seq = Reader::new(&response.data).read_int()?;
+        response.data.drain(..4);
+        return Ok((seq, response));
+    }
+
+    /* returns the update to pass on from a notification, or None if it has been seen before
+       updates are numbered one after another for every monitor, so a gap means one was missed and the whole file is asked for again.
+       The same is done when there is no copy of the file for a delta to be applied to, which is the case for the first one */
+    fn next_update(&mut self, notification: Notification) -> Result<Option<ResponseMarshal>, ClientError> {
+        let id = notification.monitor_id;
+        let last = self.monitor_seqs.get(&id).copied().unwrap_or(0);
+        if notification.seq <= last {
+            return Ok(None);
+        }
+        // a removal needs nothing before it, and neither does a change in a directory
+        let standalone = notification.update.status == StatusCode::NOT_FOUND || notification.update.status == StatusCode::ENTRY;
+        if notification.seq == last + 1 || standalone {
+            if let Some(update) = self.patch(id, notification.update)? {
+                self.monitor_seqs.insert(id, notification.seq);
+                return Ok(Some(update));
+            }
+        }
+        let (seq, update) = self.resync(id)?;
+        self.monitor_seqs.insert(id, seq.max(notification.seq));
+        self.monitor_copies.insert(id, update.data.clone());
+        return Ok(Some(update));
+    }
+
+    /* turns an update into the whole file, applying it to the copy kept for its monitor if it is a delta
+       returns None if there is no copy the delta applies to */
+    fn patch(&mut self, id: u32, update: ResponseMarshal) -> Result<Option<ResponseMarshal>, ClientError> {
+        if update.status != StatusCode::DELTA {
+            if update.status == StatusCode::GOOD {
+                self.monitor_copies.insert(id, update.data.clone());
+            } else {
+                self.monitor_copies.remove(&id);
+            }
+            return Ok(Some(update));
+        }
+        let delta = Delta::from_bytes(&update.data)?;
+        let copy = match self.monitor_copies.get_mut(&id) {
+            Some(copy) => copy,
+            None => return Ok(None),
+        };
+        if !delta.apply(copy) {
+            self.monitor_copies.remove(&id);
+            return Ok(None);
+        }
+        return Ok(Some(ResponseMarshal{status: StatusCode::GOOD, timestamp: update.timestamp, data: copy.clone()}));
+    }
+
+    // sends a request and turns any error status into an error
+    fn call(&mut self, req: RequestMarshal) -> Result<ResponseMarshal, ClientError> {
+        let response = self.send(req)?;
+        if !StatusCode::is_ok(response.status) {
+            return Err(ClientError::Status{status: response.status, message: String::from_utf8_lossy(&response.data).into_owned()});
+        }
+        return Ok(response);
+    }
+
+    /* starts a session with the server, which drops any responses it saved for an earlier session from this address
+       this is done by connect, but can be done again to start over */
+    pub fn handshake(&mut self) -> Result<(), ClientError> {
+        let mut req = self.request(RequestOperation::HANDSHAKE);
+        req.add_int(self.session_id);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // ends the session, after which the server forgets about this client
+    pub fn disconnect(mut self) -> Result<(), ClientError> {
+        let req = self.request(RequestOperation::DISCONNECT);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // reads amount bytes of a file starting from the offset
+    pub fn read(&mut self, path: &str, offset: u32, amount: u32) -> Result<FileContent, ClientError> {
+        let mut req = self.file_request(RequestOperation::READ, path);
+        req.add_int(offset);
+        req.add_int(amount);
+        let response = self.call(req)?;
+        return Ok(FileContent{data: response.data, modified: response.timestamp.unwrap_or(0)});
+    }
+
+    // reads like read does, unless the file was last modified at the given time, in which case None is returned
+    pub fn validate(&mut self, path: &str, offset: u32, amount: u32, modified: u64) -> Result<Option<FileContent>, ClientError> {
+        let mut req = self.file_request(RequestOperation::VALIDATE, path);
+        req.add_int(offset);
+        req.add_int(amount);
+        req.add_long(modified);
+        let response = self.call(req)?;
+        if response.status == StatusCode::UNCHANGED {
+            return Ok(None);
+        }
+        return Ok(Some(FileContent{data: response.data, modified: response.timestamp.unwrap_or(0)}));
+    }
+
+    // inserts data at the offset, shifting everything after it
+    pub fn insert(&mut self, path: &str, offset: u32, data: &[u8]) -> Result<(), ClientError> {
+        let mut req = self.file_request(RequestOperation::INSERT, path);
+        req.add_int(offset);
+        req.add_bytes(data);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // overwrites the bytes starting from the offset, which can't go past the end of the file
+    pub fn update(&mut self, path: &str, offset: u32, data: &[u8]) -> Result<(), ClientError> {
+        let mut req = self.file_request(RequestOperation::UPDATE, path);
+        req.add_int(offset);
+        req.add_bytes(data);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // deletes amount bytes starting from the offset
+    pub fn delete(&mut self, path: &str, offset: u32, amount: u32) -> Result<(), ClientError> {
+        let mut req = self.file_request(RequestOperation::DELETE, path);
+        req.add_int(offset);
+        req.add_int(amount);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // adds data to the end of a file, returning the offset it ended up at
+    pub fn append(&mut self, path: &str, data: &[u8]) -> Result<u64, ClientError> {
+        let mut req = self.file_request(RequestOperation::APPEND, path);
+        req.add_bytes(data);
+        let response = self.call(req)?;
+        return Ok(Reader::new(&response.data).read_long()?);
+    }
+
+    // creates a file with some initial content, replacing any existing file unless told not to
+    pub fn create(&mut self, path: &str, content: &[u8], fail_if_exists: bool) -> Result<(), ClientError> {
+        let mut req = self.file_request(RequestOperation::CREATE, path);
+        req.add_byte(if fail_if_exists { CreateFlag::FAIL_IF_EXISTS } else { 0 });
+        req.add_bytes(content);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    pub fn remove(&mut self, path: &str) -> Result<(), ClientError> {
+        let req = self.file_request(RequestOperation::REMOVE, path);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // lists the entries of a directory, sorted by name
+    pub fn list(&mut self, path: &str) -> Result<Vec<DirEntry>, ClientError> {
+        let req = self.file_request(RequestOperation::LIST, path);
+        let response = self.call(req)?;
+        let mut reader = Reader::new(&response.data);
+        let count = reader.read_int()?;
+        let mut entries: Vec<DirEntry> = Vec::new();
+        for _ in 0..count {
+            let name = String::from_utf8_lossy(reader.read_bytes()?).into_owned();
+            let entry_type = reader.read_byte()?;
+            let size = reader.read_long()?;
+            let modified = reader.read_long()?;
+            entries.push(DirEntry{name, entry_type, size, modified});
+        }
+        return Ok(entries);
+    }
+
+    pub fn stat(&mut self, path: &str) -> Result<Metadata, ClientError> {
+        let req = self.file_request(RequestOperation::STAT, path);
+        let response = self.call(req)?;
+        let mut reader = Reader::new(&response.data);
+        let entry_type = reader.read_byte()?;
+        let size = reader.read_long()?;
+        let modified = reader.read_long()?;
+        let permissions = reader.read_int()?;
+        return Ok(Metadata{entry_type, size, modified, permissions});
+    }
+
+    /* monitors a file for the given interval, calling on_event with every update the server pushes
+       this blocks until the interval has passed or the file is removed, or until on_event returns false, which cancels the monitor.
+       Only what changed is sent by the server, which is applied to a copy of the file so that on_event still gets all of it */
+    pub fn monitor(&mut self, path: &str, interval: Duration, on_event: impl FnMut(MonitorEvent) -> bool) -> Result<(), ClientError> {
+        return self.start_monitor(path, interval, MonitorFlag::DELTA, None, on_event);
+    }
+
+    /* monitors a directory like monitor does a file, calling on_event with an Entry for every file in it that is created, changed or removed
+       files in the directories under it are included as well if it is recursive. monitor can be used on a directory too, which isn't recursive */
+    pub fn monitor_directory(&mut self, path: &str, recursive: bool, interval: Duration, on_event: impl FnMut(MonitorEvent) -> bool) -> Result<(), ClientError> {
+        let flags = if recursive { MonitorFlag::RECURSIVE } else { 0 };
+        return self.start_monitor(path, interval, flags, None, on_event);
+    }
+
+    /* monitors length bytes of a file starting from the offset, which is like monitor except that only changes to those bytes are pushed
+       the range moves along as bytes are inserted or deleted before it, and grows or shrinks with changes within it.
+       on_event is given the content of the range rather than the whole file */
+    pub fn monitor_range(&mut self, path: &str, offset: u32, length: u32, interval: Duration, on_event: impl FnMut(MonitorEvent) -> bool) -> Result<(), ClientError> {
+        return self.start_monitor(path, interval, MonitorFlag::DELTA, Some((offset, length)), on_event);
+    }
+
+    fn start_monitor(&mut self, path: &str, interval: Duration, flags: u8, range: Option<(u32, u32)>, mut on_event: impl FnMut(MonitorEvent) -> bool) -> Result<(), ClientError> {
+        let mut req = self.file_request(RequestOperation::MONITOR, path);
+        req.add_int(interval.as_millis().min(u32::MAX as u128) as u32);
+        req.add_byte(flags);
+        if let Some((offset, length)) = range {
+            req.add_int(offset);
+            req.add_int(length);
+        }
+        self.call(req)?;
+
+        self.monitoring = true;
+        let result = self.watch(path, Instant::now() + interval, &mut on_event);
+        // updates that are still queued belong to a monitor that is over, and so do the copies of the file
+        self.monitoring = false;
+        self.queued.clear();
+        self.monitor_copies.clear();
+        return result;
+    }
+
+    // passes on the updates of a monitor until it ends, starting with those that arrived while waiting for a response
+    fn watch(&mut self, path: &str, wait_until: Instant, on_event: &mut impl FnMut(MonitorEvent) -> bool) -> Result<(), ClientError> {
+        loop {
+            let notification = match self.queued.pop_front() {
+                Some(notification) => notification,
+                // the interval passing is the only way out other than the file being removed
+                None => match self.receive(wait_until)? {
+                    // anything else is a late response to an earlier request
+                    Some(buf) if Notification::is_notification(&buf) => {
+                        let notification = Notification::from_bytes(&buf)?;
+                        self.acknowledge(&notification)?;
+                        notification
+                    },
+                    Some(_) => continue,
+                    None => break,
+                },
+            };
+            let response = match self.next_update(notification)? {
+                Some(response) => response,
+                None => continue,
+            };
+            if response.status == StatusCode::NOT_FOUND {
+                on_event(MonitorEvent::Removed);
+                break;
+            } else if response.status == StatusCode::ENTRY {
+                if !on_event(MonitorEvent::Entry(EntryChange::from_bytes(&response.data)?)) {
+                    return self.unmonitor(path);
+                }
+            } else if StatusCode::is_ok(response.status) {
+                let content = FileContent{data: response.data, modified: response.timestamp.unwrap_or(0)};
+                if !on_event(MonitorEvent::Changed(content)) {
+                    return self.unmonitor(path);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // cancels the monitor on a file before its interval runs out
+    pub fn unmonitor(&mut self, path: &str) -> Result<(), ClientError> {
+        let req = self.file_request(RequestOperation::UNMONITOR, path);
+        self.call(req)?;
+        return Ok(());
+    }
+
+    // lists the monitors this client has that haven't expired, sorted by path
+    pub fn list_monitors(&mut self) -> Result<Vec<MonitorInfo>, ClientError> {
+        let req = self.request(RequestOperation::LIST_MONITORS);
+        let response = self.call(req)?;
+        let mut reader = Reader::new(&response.data);
+        let count = reader.read_int()?;
+        let mut monitors: Vec<MonitorInfo> = Vec::new();
+        for _ in 0..count {
+            let path = String::from_utf8_lossy(reader.read_bytes()?).into_owned();
+            let remaining = Duration::from_millis(reader.read_int()? as u64);
+            monitors.push(MonitorInfo{path, remaining});
+        }
+        return Ok(monitors);
+    }
+}
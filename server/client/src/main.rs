@@ -0,0 +1,187 @@
+use std::io::{self, Read, Write};
+use std::process;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use client::{Client, ClientError, EntryEvent, EntryType, MonitorEvent, StatusCode};
+
+/* this defines the arguments that the client accepts
+   unlike the Go client, every invocation carries out a single operation, so it can be used from scripts */
+#[derive(Parser, Debug)]
+#[command(about, long_about = None, after_help = EXIT_CODES)]
+struct Args {
+    /// Server Host
+    #[arg(short, long, default_value = "127.0.0.1")]
+    server_host: String,
+
+    /// Server Port
+    #[arg(short, long, default_value_t = 45600)]
+    port: u16,
+
+    /// Response timeout in milliseconds
+    #[arg(short, long, default_value_t = 3000)]
+    timeout: u64,
+
+    /// Number of times a request is sent before giving up
+    #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    retries: u32,
+
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the content of a file, from the offset to the end unless an amount is given
+    Read {
+        path: String,
+        #[arg(default_value_t = 0)]
+        offset: u32,
+        amount: Option<u32>
+    },
+    /// Insert data at the offset, read from stdin if not given
+    Insert {
+        path: String,
+        offset: u32,
+        data: Option<String>
+    },
+    /// Overwrite the file starting from the offset, with data read from stdin if not given
+    Update {
+        path: String,
+        offset: u32,
+        data: Option<String>
+    },
+    /// Delete an amount of bytes starting from the offset
+    Delete {
+        path: String,
+        offset: u32,
+        amount: u32
+    },
+    /// Print the content of a file (or of the range given by an offset and a length) every time it changes, or every file that changes in a directory, until the interval (in milliseconds) passes or the file is removed
+    Monitor {
+        path: String,
+        interval: u64,
+        #[arg(requires = "length")]
+        offset: Option<u32>,
+        length: Option<u32>,
+        /// Include the files in every directory under a monitored directory
+        #[arg(short = 'R', long, conflicts_with = "offset")]
+        recursive: bool
+    },
+    /// Print the type, size, modification time and permissions of a file or directory
+    Stat {
+        path: String
+    }
+}
+
+const EXIT_CODES: &str = "Exit codes:
+  0   the operation succeeded
+  2   the arguments were invalid
+  3   the server didn't respond
+  4   the socket failed or the response was malformed
+  10+ the server replied with an error status, which is added to 10 (e.g. 15 for NOT_FOUND, 16 for OUT_OF_RANGE)";
+
+// the exit code that tells a script why the operation failed
+fn exit_code(err: &ClientError) -> i32 {
+    match err {
+        ClientError::Timeout => 3,
+        ClientError::Io(_) | ClientError::Malformed(_) => 4,
+        ClientError::Status { status, .. } => 10 + *status as i32,
+    }
+}
+
+// data is taken from the command line, or all of stdin if it isn't there
+fn data_or_stdin(data: Option<String>) -> Result<Vec<u8>, ClientError> {
+    if let Some(data) = data {
+        return Ok(data.into_bytes());
+    }
+    let mut buf: Vec<u8> = Vec::new();
+    io::stdin().read_to_end(&mut buf)?;
+    return Ok(buf);
+}
+
+// carries out the operation, printing its result to stdout
+fn run(client: &mut Client, command: Command) -> Result<(), ClientError> {
+    let mut stdout = io::stdout();
+    match command {
+        Command::Read { path, offset, amount } => {
+            // without an amount, everything from the offset to the end of the file is read
+            let amount = match amount {
+                Some(amount) => amount,
+                None => client.stat(&path)?.size.saturating_sub(offset as u64).min(u32::MAX as u64) as u32,
+            };
+            let content = client.read(&path, offset, amount)?;
+            stdout.write_all(&content.data)?;
+        },
+        Command::Insert { path, offset, data } => client.insert(&path, offset, &data_or_stdin(data)?)?,
+        Command::Update { path, offset, data } => client.update(&path, offset, &data_or_stdin(data)?)?,
+        Command::Delete { path, offset, amount } => client.delete(&path, offset, amount)?,
+        Command::Monitor { path, interval, offset, length, recursive } => {
+            let mut removed = false;
+            let on_event = |event| match event {
+                // every update is the whole file, followed by a newline so that updates can be told apart
+                MonitorEvent::Changed(content) => {
+                    let _ = stdout.write_all(&content.data);
+                    let _ = stdout.write_all(b"\n");
+                    let _ = stdout.flush();
+                    true
+                },
+                MonitorEvent::Removed => {
+                    removed = true;
+                    false
+                },
+                // a file in a monitored directory is printed along with what happened to it
+                MonitorEvent::Entry(change) => {
+                    let event = match change.event {
+                        EntryEvent::CREATED => "created",
+                        EntryEvent::REMOVED => "removed",
+                        _ => "changed",
+                    };
+                    let _ = writeln!(stdout, "{event} {}", change.name);
+                    let _ = stdout.flush();
+                    true
+                },
+            };
+            let interval = Duration::from_millis(interval);
+            match (offset, length) {
+                (Some(offset), Some(length)) => client.monitor_range(&path, offset, length, interval, on_event)?,
+                _ if recursive => client.monitor_directory(&path, true, interval, on_event)?,
+                _ => client.monitor(&path, interval, on_event)?,
+            }
+            if removed {
+                return Err(ClientError::Status{status: StatusCode::NOT_FOUND, message: String::from("File has been removed")});
+            }
+        },
+        Command::Stat { path } => {
+            let metadata = client.stat(&path)?;
+            let entry_type = if metadata.entry_type == EntryType::DIRECTORY { "directory" } else { "file" };
+            writeln!(stdout, "{entry_type} {} {} {:o}", metadata.size, metadata.modified, metadata.permissions)?;
+        },
+    }
+    stdout.flush()?;
+    return Ok(());
+}
+
+fn main() {
+    // parse cli arguments, clap exits with a code of 2 if they are invalid
+    let args: Args = Args::parse();
+
+    let server_address = format!("{}:{}", args.server_host, args.port);
+    let mut client = match Client::builder().timeout(Duration::from_millis(args.timeout)).retries(args.retries).connect(&server_address) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Couldn't connect to {server_address}: {err}");
+            process::exit(exit_code(&err));
+        }
+    };
+
+    let result = run(&mut client, args.command);
+    // the session is ended either way, the outcome of the operation is what decides the exit code
+    if let Err(err) = client.disconnect() {
+        eprintln!("Couldn't disconnect from {server_address}: {err}");
+    }
+    if result.is_err() {
+        let err = result.unwrap_err();
+        eprintln!("{err}");
+        process::exit(exit_code(&err));
+    }
+}
@@ -0,0 +1,530 @@
+/* The wire format shared by the server and the Rust client, so that the two can't drift apart
+   Every request starts with a 4-byte request number, a 4-byte acknowledgement and the operation as a byte,
+   followed by the fields of the operation. Integers are big-endian, and strings and byte arrays are prefixed by their length as a u32.
+   Every response starts with the status as a byte, followed by an optional 8-byte timestamp and the data */
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Operation constants for easy access and modification if need be
+#[non_exhaustive]
+pub struct RequestOperation;
+impl RequestOperation {
+    pub const HANDSHAKE: u8 = 0;
+    pub const DISCONNECT: u8 = 1;
+    pub const READ: u8 = 2;
+    pub const INSERT: u8 = 3;
+    pub const UPDATE: u8 = 4;
+    pub const DELETE: u8 = 5;
+    pub const MONITOR: u8 = 6;
+    pub const VALIDATE: u8 = 7;
+    pub const CREATE: u8 = 8;
+    pub const REMOVE: u8 = 9;
+    pub const LIST: u8 = 10;
+    pub const STAT: u8 = 11;
+    pub const APPEND: u8 = 12;
+    pub const RESEND: u8 = 13;
+    pub const UNMONITOR: u8 = 14;
+    pub const LIST_MONITORS: u8 = 15;
+    pub const MONITOR_ACK: u8 = 16;
+    pub const RESYNC: u8 = 17;
+}
+
+// Types of entries sent back by list and stat
+#[non_exhaustive]
+pub struct EntryType;
+impl EntryType {
+    pub const FILE: u8 = 0;
+    pub const DIRECTORY: u8 = 1;
+}
+
+// Flags that can be combined in the flags byte of a create request
+#[non_exhaustive]
+pub struct CreateFlag;
+impl CreateFlag {
+    pub const FAIL_IF_EXISTS: u8 = 1;
+}
+
+/* Flags that can be combined in the optional flags byte after the interval of a monitor request
+   an offset and a length can follow the flags, in which case only that range of the file is monitored */
+#[non_exhaustive]
+pub struct MonitorFlag;
+impl MonitorFlag {
+    pub const DELTA: u8 = 1; // changes are pushed as a Delta rather than the whole file
+    pub const RECURSIVE: u8 = 2; // a directory is monitored along with every directory under it
+}
+
+// What happened to a file in a monitored directory
+#[non_exhaustive]
+pub struct EntryEvent;
+impl EntryEvent {
+    pub const CREATED: u8 = 0;
+    pub const CHANGED: u8 = 1;
+    pub const REMOVED: u8 = 2;
+}
+
+// A struct to represent the response that is sent to clients
+#[derive(Clone)]
+pub struct ResponseMarshal {
+    pub status: u8,
+    pub timestamp: Option<u64>, // last modification time of the file, only included for file content
+    pub data: Vec<u8> // raw bytes, which are either file content or a readable message
+}
+
+// Converts the struct into a byte buffer for sending to a client
+impl ResponseMarshal {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf : Vec<u8> = Vec::new();
+        buf.push(self.status);
+        // the timestamp goes before the data as 8 bytes, most significant byte first
+        if let Some(timestamp) = self.timestamp {
+            buf.extend(timestamp.to_be_bytes());
+        }
+        buf.extend(&self.data);
+        return buf;
+    }
+
+    // the number of bytes this response takes up when sent
+    pub fn size(&self) -> usize {
+        let timestamp_size = if self.timestamp.is_some() { 8 } else { 0 };
+        return 1 + timestamp_size + self.data.len();
+    }
+
+    /* the reverse of to_bytes, for the client side
+       a response can't say whether it has a timestamp, so the caller has to know from the request it sent.
+       Even then, only successful responses and deltas carry one */
+    pub fn from_bytes(buf: &[u8], has_timestamp: bool) -> Result<ResponseMarshal, DecodeError> {
+        let mut reader = Reader::new(buf);
+        let status = reader.read_byte()?;
+        let mut timestamp: Option<u64> = None;
+        if has_timestamp && (StatusCode::is_ok(status) || status == StatusCode::DELTA) {
+            timestamp = Some(reader.read_long()?);
+        }
+        let data = reader.take(reader.remaining())?.to_vec();
+        return Ok(ResponseMarshal{status, timestamp, data});
+    }
+}
+
+// A request being built up field by field before it is sent to the server
+pub struct RequestMarshal {
+    pub number: u32,
+    pub op: u8,
+    buf: Vec<u8>
+}
+
+impl RequestMarshal {
+    // always starts with a 4-byte request number, a 4-byte acknowledgement and then the operation as a byte
+    pub fn new(number: u32, ack: u32, op: u8) -> RequestMarshal {
+        let mut req = RequestMarshal{number, op, buf: Vec::new()};
+        req.add_int(number);
+        req.add_int(ack);
+        req.add_byte(op);
+        return req;
+    }
+
+    // marshals an integer into 4 bytes
+    pub fn add_int(&mut self, val: u32) {
+        self.buf.extend(val.to_be_bytes());
+    }
+
+    // marshals a long integer into 8 bytes, used for timestamps
+    pub fn add_long(&mut self, val: u64) {
+        self.buf.extend(val.to_be_bytes());
+    }
+
+    // marshals raw bytes (or a string) into 4 + n bytes
+    pub fn add_bytes(&mut self, val: &[u8]) {
+        self.add_int(val.len() as u32);
+        self.buf.extend(val);
+    }
+
+    pub fn add_byte(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    // only reads, validations and resyncs have file content along with a timestamp in their response
+    pub fn has_timestamp(&self) -> bool {
+        return self.op == RequestOperation::READ || self.op == RequestOperation::VALIDATE || self.op == RequestOperation::RESYNC;
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        return &self.buf;
+    }
+}
+
+/* An update pushed to a client monitoring a file, which is wrapped with the NOTIFY status, the id of the monitor and a sequence number
+   the sequence numbers of a monitor go up by one with every update, so a client can tell when it has missed one.
+   The update itself is either the new content with its modification time, a DELTA with its modification time for monitors that asked for them,
+   or a NOT_FOUND once the file has been removed. Monitors on a directory are sent an ENTRY for every file in it that changes instead.
+   A client that has missed one sends a RESYNC, which is answered like a read with the latest sequence number before the content */
+pub struct Notification {
+    pub monitor_id: u32,
+    pub seq: u32,
+    pub update: ResponseMarshal
+}
+
+impl Notification {
+    pub fn to_response(&self) -> ResponseMarshal {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend(self.monitor_id.to_be_bytes());
+        data.extend(self.seq.to_be_bytes());
+        data.extend(self.update.to_bytes());
+        return ResponseMarshal{status: StatusCode::NOTIFY, timestamp: None, data};
+    }
+
+    pub fn is_notification(buf: &[u8]) -> bool {
+        return buf.first() == Some(&StatusCode::NOTIFY);
+    }
+
+    // the reverse of to_response, for a message that has already been checked to be a notification
+    pub fn from_bytes(buf: &[u8]) -> Result<Notification, DecodeError> {
+        let mut reader = Reader::new(buf);
+        reader.read_byte()?;
+        let monitor_id = reader.read_int()?;
+        let seq = reader.read_int()?;
+        // updates always come with the modification time of the content
+        let update = ResponseMarshal::from_bytes(reader.take(reader.remaining())?, true)?;
+        return Ok(Notification{monitor_id, seq, update});
+    }
+}
+
+/* A change to a file, pushed instead of the whole file to monitors that asked for deltas
+   it is marshalled as the operation as a byte, the offset and the number of bytes removed there as longs,
+   the bytes put in their place and finally the new length of the file as a long. Changes that don't fit this, like a file being replaced,
+   are pushed as the whole file instead */
+#[derive(Clone, Debug)]
+pub struct Delta {
+    pub op: u8, // the operation that made the change
+    pub offset: u64,
+    pub removed: u64, // the number of bytes taken out at the offset, which is the length of the data for an update
+    pub data: Vec<u8>, // the bytes that went in at the offset
+    pub length: u64 // the length of the file after the change
+}
+
+impl Delta {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.push(self.op);
+        buf.extend(self.offset.to_be_bytes());
+        buf.extend(self.removed.to_be_bytes());
+        buf.extend((self.data.len() as u32).to_be_bytes());
+        buf.extend(&self.data);
+        buf.extend(self.length.to_be_bytes());
+        return buf;
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Delta, DecodeError> {
+        let mut reader = Reader::new(buf);
+        let op = reader.read_byte()?;
+        let offset = reader.read_long()?;
+        let removed = reader.read_long()?;
+        let data = reader.read_bytes()?.to_vec();
+        let length = reader.read_long()?;
+        return Ok(Delta{op, offset, removed, data, length});
+    }
+
+    /* patches a copy of the file, returning false if the copy doesn't match what the change was made to
+       the copy is left as it was in that case */
+    pub fn apply(&self, content: &mut Vec<u8>) -> bool {
+        let len = content.len() as u64;
+        if self.offset > len || self.removed > len - self.offset || len - self.removed + self.data.len() as u64 != self.length {
+            return false;
+        }
+        let start = self.offset as usize;
+        content.splice(start..start + self.removed as usize, self.data.iter().copied());
+        return true;
+    }
+}
+
+/* A file in a monitored directory that has been created, changed or removed
+   it is marshalled as the event as a byte followed by the length-prefixed path of the file from the directory */
+#[derive(Clone, Debug)]
+pub struct EntryChange {
+    pub event: u8, // one of the EntryEvent constants
+    pub name: String
+}
+
+impl EntryChange {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.push(self.event);
+        buf.extend((self.name.len() as u32).to_be_bytes());
+        buf.extend(self.name.as_bytes());
+        return buf;
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<EntryChange, DecodeError> {
+        let mut reader = Reader::new(buf);
+        let event = reader.read_byte()?;
+        let name = String::from_utf8_lossy(reader.read_bytes()?).into_owned();
+        return Ok(EntryChange{event, name});
+    }
+}
+
+/* One piece of a response that was too large for a single datagram
+   it has a 13 byte header: the FRAGMENT status, the message id, the sequence number of the fragment and the total number of fragments */
+pub struct Fragment<'a> {
+    pub id: u32,
+    pub seq: u32,
+    pub total: u32,
+    pub chunk: &'a [u8]
+}
+
+impl<'a> Fragment<'a> {
+    pub const HEADER_SIZE: usize = 13;
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.push(StatusCode::FRAGMENT);
+        buf.extend(self.id.to_be_bytes());
+        buf.extend(self.seq.to_be_bytes());
+        buf.extend(self.total.to_be_bytes());
+        buf.extend(self.chunk);
+        return buf;
+    }
+
+    // returns None if the datagram isn't a fragment at all
+    pub fn from_bytes(buf: &'a [u8]) -> Option<Fragment<'a>> {
+        if buf.len() < Fragment::HEADER_SIZE || buf[0] != StatusCode::FRAGMENT {
+            return None;
+        }
+        let mut reader = Reader::new(&buf[1..]);
+        let id = reader.read_int().ok()?;
+        let seq = reader.read_int().ok()?;
+        let total = reader.read_int().ok()?;
+        return Some(Fragment{id, seq, total, chunk: &buf[Fragment::HEADER_SIZE..]});
+    }
+}
+
+/* Status type defined here and corresponds to what the client has
+   Every status other than GOOD and UNCHANGED is an error, and the data that follows it is an optional readable message */
+#[non_exhaustive]
+pub struct StatusCode;
+impl StatusCode {
+    pub const BAD: u8 = 0; // generic error that doesn't fit any of the more specific ones
+    pub const GOOD: u8 = 1;
+    pub const UNCHANGED: u8 = 2;
+    pub const OUTSIDE_ROOT: u8 = 3;
+    pub const MALFORMED: u8 = 4;
+    pub const NOT_FOUND: u8 = 5;
+    pub const OUT_OF_RANGE: u8 = 6;
+    pub const PERMISSION_DENIED: u8 = 7;
+    pub const IO_ERROR: u8 = 8;
+    pub const UNSUPPORTED: u8 = 9;
+    pub const ALREADY_EXISTS: u8 = 10;
+    pub const FRAGMENT: u8 = 11; // not a response by itself, this marks a datagram as one piece of a larger response
+    pub const NOTIFY: u8 = 12; // not a response by itself either, this marks an update pushed to a client monitoring a file
+    pub const DELTA: u8 = 13; // only found inside a notification, this marks an update that describes a change instead of carrying the whole file
+    pub const ENTRY: u8 = 14; // only found inside a notification as well, this marks a change to a file in a monitored directory
+    pub const NOT_A_DIRECTORY: u8 = 15; // the path exists but is a file, where a directory is needed
+    pub const STALE: u8 = 16; // under at-most-once semantics, the request is older than any saved response and won't be carried out again
+
+    // any other status means the server couldn't carry out the request
+    pub fn is_ok(status: u8) -> bool {
+        return status == StatusCode::GOOD || status == StatusCode::UNCHANGED;
+    }
+
+    // returns a readable name of a status for logging
+    pub fn name(status: u8) -> &'static str {
+        match status {
+            StatusCode::GOOD => "GOOD",
+            StatusCode::UNCHANGED => "UNCHANGED",
+            StatusCode::OUTSIDE_ROOT => "OUTSIDE_ROOT",
+            StatusCode::MALFORMED => "MALFORMED",
+            StatusCode::NOT_FOUND => "NOT_FOUND",
+            StatusCode::OUT_OF_RANGE => "OUT_OF_RANGE",
+            StatusCode::PERMISSION_DENIED => "PERMISSION_DENIED",
+            StatusCode::IO_ERROR => "IO_ERROR",
+            StatusCode::UNSUPPORTED => "UNSUPPORTED",
+            StatusCode::ALREADY_EXISTS => "ALREADY_EXISTS",
+            StatusCode::FRAGMENT => "FRAGMENT",
+            StatusCode::NOTIFY => "NOTIFY",
+            StatusCode::DELTA => "DELTA",
+            StatusCode::ENTRY => "ENTRY",
+            StatusCode::NOT_A_DIRECTORY => "NOT_A_DIRECTORY",
+            StatusCode::STALE => "STALE",
+            _ => "BAD",
+        }
+    }
+
+    // picks the status that best describes a filesystem error
+    pub fn from_io_error(err: &Error) -> u8 {
+        match err.kind() {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::PermissionDenied => StatusCode::PERMISSION_DENIED,
+            ErrorKind::AlreadyExists => StatusCode::ALREADY_EXISTS,
+            _ => StatusCode::IO_ERROR,
+        }
+    }
+}
+
+// Reasons why a request could not be unmarshalled
+#[derive(Debug)]
+pub enum DecodeError {
+    Truncated { needed: usize, remaining: usize }, // a field is cut short by the end of the request
+    Overlong { len: u32, remaining: usize }, // a length prefix claims more bytes than the request has left
+    Trailing { remaining: usize } // bytes are left over after the last field, so the request isn't framed the way it claims
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, remaining } => write!(f, "needed {needed} bytes but only {remaining} are left"),
+            DecodeError::Overlong { len, remaining } => write!(f, "length of {len} exceeds the {remaining} bytes left"),
+            DecodeError::Trailing { remaining } => write!(f, "{remaining} bytes are left over after the last field"),
+        }
+    }
+}
+
+// Reader unmarshals the fields of a request (or anything else laid out the same way) one at a time
+pub struct Reader<'a> {
+    buf: &'a [u8], // this is exactly the data received
+    i: usize // this is the current index in the buffer when unmarshalling
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        return Reader{buf, i: 0};
+    }
+
+    // the number of bytes that haven't been unmarshalled yet
+    pub fn remaining(&self) -> usize {
+        return self.buf.len() - self.i;
+    }
+
+    // takes the next n bytes from the buffer, failing if the request is too short
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        let remaining = self.remaining();
+        if n > remaining {
+            return Err(DecodeError::Truncated{needed: n, remaining});
+        }
+        let val: &'a [u8] = &self.buf[self.i..self.i+n];
+        self.i += n;
+        return Ok(val);
+    }
+
+    // unmarshalls an integer from the buffer
+    pub fn read_int(&mut self) -> Result<u32, DecodeError> {
+        // these operations are a reverse of what RequestMarshal does
+        let bytes = self.take(4)?;
+        let mut c : u32 = bytes[3] as u32;
+        c += (bytes[2] as u32) << 8;
+        c += (bytes[1] as u32) << 16;
+        c += (bytes[0] as u32) << 24;
+        return Ok(c);
+    }
+
+    // unmarshalls a long integer (used for timestamps) from the buffer
+    pub fn read_long(&mut self) -> Result<u64, DecodeError> {
+        // the most significant 4 bytes are sent first
+        let high: u64 = self.read_int()? as u64;
+        let low: u64 = self.read_int()? as u64;
+        return Ok((high << 32) + low);
+    }
+
+    /* unmarshalls a length-prefixed byte array from the buffer
+       the bytes are returned as is, since they could be file content that isn't text */
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        // read the length first and make sure that many bytes were actually sent
+        let len : u32 = self.read_int()?;
+        let remaining = self.remaining();
+        if len as usize > remaining {
+            return Err(DecodeError::Overlong{len, remaining});
+        }
+        return self.take(len as usize);
+    }
+
+    // checks that every byte has been unmarshalled, which is done after the last field before acting on anything
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        let remaining = self.remaining();
+        if remaining > 0 {
+            return Err(DecodeError::Trailing{remaining});
+        }
+        return Ok(());
+    }
+
+    // used to read 1 byte from the buffer. used for reading the operation type
+    pub fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        let bytes = self.take(1)?;
+        return Ok(bytes[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reader_rejects_fields_that_run_past_the_end() {
+        let mut reader = Reader::new(&[0, 0, 1]);
+        assert!(matches!(reader.read_int(), Err(DecodeError::Truncated{needed: 4, remaining: 3})));
+        // a length prefix claiming more than what is left is caught before anything is taken
+        let mut reader = Reader::new(&[0, 0, 0, 9, b'a', b'b']);
+        assert!(matches!(reader.read_bytes(), Err(DecodeError::Overlong{len: 9, remaining: 2})));
+        let mut reader = Reader::new(&[0, 0, 0, 2, b'a', b'b', 7]);
+        assert_eq!(reader.read_bytes().ok(), Some(&b"ab"[..]));
+        assert!(matches!(reader.finish(), Err(DecodeError::Trailing{remaining: 1})));
+        assert_eq!(reader.read_byte().ok(), Some(7));
+        assert!(reader.finish().is_ok());
+        assert!(matches!(reader.read_byte(), Err(DecodeError::Truncated{needed: 1, remaining: 0})));
+    }
+
+    #[test]
+    fn io_errors_map_to_their_status() {
+        assert_eq!(StatusCode::from_io_error(&Error::from(ErrorKind::NotFound)), StatusCode::NOT_FOUND);
+        assert_eq!(StatusCode::from_io_error(&Error::from(ErrorKind::PermissionDenied)), StatusCode::PERMISSION_DENIED);
+        assert_eq!(StatusCode::from_io_error(&Error::from(ErrorKind::AlreadyExists)), StatusCode::ALREADY_EXISTS);
+        // anything without a status of its own is reported as an I/O error
+        assert_eq!(StatusCode::from_io_error(&Error::from(ErrorKind::UnexpectedEof)), StatusCode::IO_ERROR);
+        assert!(StatusCode::is_ok(StatusCode::UNCHANGED));
+        assert!(!StatusCode::is_ok(StatusCode::NOT_FOUND));
+        assert_eq!(StatusCode::name(StatusCode::OUT_OF_RANGE), "OUT_OF_RANGE");
+        assert_eq!(StatusCode::name(200), "BAD");
+    }
+
+    #[test]
+    fn fragments_survive_marshalling() {
+        let buf = Fragment{id: 7, seq: 2, total: 3, chunk: b"abc"}.to_bytes();
+        assert_eq!(buf.len(), Fragment::HEADER_SIZE + 3);
+        let fragment = Fragment::from_bytes(&buf).unwrap();
+        assert_eq!((fragment.id, fragment.seq, fragment.total, fragment.chunk), (7, 2, 3, &b"abc"[..]));
+        // a whole response is never taken for a fragment, even one that happens to be as long as a header
+        assert!(Fragment::from_bytes(&ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: vec![0; 12]}.to_bytes()).is_none());
+        assert!(Fragment::from_bytes(&buf[..Fragment::HEADER_SIZE - 1]).is_none());
+    }
+
+    fn delta(op: u8, offset: u64, removed: u64, data: &[u8], length: u64) -> Delta {
+        return Delta{op, offset, removed, data: data.to_vec(), length};
+    }
+
+    #[test]
+    fn delta_patches_a_matching_copy() {
+        let mut content = b"hello world".to_vec();
+        assert!(delta(RequestOperation::INSERT, 5, 0, b",", 12).apply(&mut content));
+        assert_eq!(content, b"hello, world");
+        assert!(delta(RequestOperation::UPDATE, 0, 1, b"J", 12).apply(&mut content));
+        assert_eq!(content, b"Jello, world");
+        assert!(delta(RequestOperation::DELETE, 5, 7, b"", 5).apply(&mut content));
+        assert_eq!(content, b"Jello");
+        assert!(delta(RequestOperation::INSERT, 5, 0, b"!", 6).apply(&mut content));
+        assert_eq!(content, b"Jello!");
+    }
+
+    #[test]
+    fn delta_leaves_a_mismatched_copy_alone() {
+        let mut content = b"hello".to_vec();
+        // the length after the change doesn't match, so the copy was out of date
+        assert!(!delta(RequestOperation::INSERT, 0, 0, b"a", 7).apply(&mut content));
+        assert!(!delta(RequestOperation::INSERT, 6, 0, b"a", 6).apply(&mut content));
+        assert!(!delta(RequestOperation::DELETE, 3, 3, b"", 2).apply(&mut content));
+        assert_eq!(content, b"hello");
+    }
+
+    #[test]
+    fn delta_survives_marshalling() {
+        let original = delta(RequestOperation::UPDATE, 3, 2, b"xy", 10);
+        let decoded = Delta::from_bytes(&original.to_bytes()).ok().unwrap();
+        assert_eq!((decoded.op, decoded.offset, decoded.removed, decoded.data, decoded.length), (original.op, original.offset, original.removed, original.data.clone(), original.length));
+        assert!(Delta::from_bytes(&original.to_bytes()[..10]).is_err());
+    }
+}
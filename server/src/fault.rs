@@ -0,0 +1,293 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::get_time;
+
+/* Faults injected into the datagrams the server receives and sends, so that message loss can be reproduced on demand
+   each fraction is the chance of the fault happening to any one datagram. Streams are reliable, so they are left alone */
+#[derive(Clone, Copy)]
+pub struct FaultPolicy {
+    pub drop_requests: f64, // received datagrams that are dropped before being serviced
+    pub drop_replies: f64, // datagrams that are never sent
+    pub duplicate_replies: f64, // datagrams that are sent twice
+    pub delay_replies: f64, // datagrams that are held back for a random time up to max_delay
+    pub max_delay: Duration,
+    pub seed: u64
+}
+
+impl FaultPolicy {
+    // whether any fault is injected at all
+    pub fn is_active(&self) -> bool {
+        return self.drop_requests > 0.0 || self.drop_replies > 0.0 || self.duplicate_replies > 0.0 || self.delay_replies > 0.0;
+    }
+}
+
+// folds a value into a hash with the splitmix64 finalizer, so that similar inputs still give unrelated hashes
+fn mix(hash: u64, value: u64) -> u64 {
+    let mut state = (hash ^ value).wrapping_add(0x9e3779b97f4a7c15);
+    state = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    state = (state ^ (state >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return state ^ (state >> 31);
+}
+
+/* xorshift64*, which is written out here since the faults only need to be reproducible from a seed, not unpredictable
+   the seed is scrambled first, so that similar seeds still give unrelated faults */
+struct Rng {
+    state: u64
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        let mut state = mix(seed, 0);
+        // a state of 0 would only ever give 0
+        if state == 0 {
+            state = 0x9e3779b97f4a7c15;
+        }
+        return Rng{state};
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545f4914f6cdd1d);
+    }
+
+    // a number in [0, 1), from the top 53 bits which is all an f64 can hold
+    fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    // true with the given chance. nothing is drawn for a fault that is turned off, so it doesn't change the faults that are on
+    fn chance(&mut self, fraction: f64) -> bool {
+        if fraction <= 0.0 {
+            return false;
+        }
+        return self.next_f64() < fraction;
+    }
+}
+
+/* What a datagram is told apart by when its faults are decided: the request it is or answers, the monitor update it carries
+   and which fragment of a message it is. Its faults don't depend on when it happens to be sent then,
+   so the same datagrams get the same faults however the threads of the server interleave */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DatagramKey(u64);
+
+impl DatagramKey {
+    const REQUEST: u64 = 0;
+    const REPLY: u64 = 1;
+    const NOTIFICATION: u64 = 2;
+
+    // the request number, the acknowledgement and the operation a request starts with, or as much of them as it has
+    fn header(kind: u64, request: &[u8]) -> DatagramKey {
+        let mut hash = mix(kind, request.len().min(9) as u64);
+        for byte in request.iter().take(9) {
+            hash = mix(hash, *byte as u64);
+        }
+        return DatagramKey(hash);
+    }
+
+    // a received request
+    pub fn request(request: &[u8]) -> DatagramKey {
+        return DatagramKey::header(DatagramKey::REQUEST, request);
+    }
+
+    // the reply to a request
+    pub fn reply(request: &[u8]) -> DatagramKey {
+        return DatagramKey::header(DatagramKey::REPLY, request);
+    }
+
+    // an update sent by a monitor
+    pub fn notification(monitor_id: u32, seq: u32) -> DatagramKey {
+        return DatagramKey(mix(mix(DatagramKey::NOTIFICATION, monitor_id as u64), seq as u64));
+    }
+
+    // one of the fragments that the datagram is split into
+    pub fn fragment(&self, seq: u32) -> DatagramKey {
+        return DatagramKey(mix(self.0, seq as u64));
+    }
+}
+
+// how often each datagram has come up so far, and when it last did
+struct Attempts {
+    seen: HashMap<(SocketAddr, DatagramKey), (u64, u128)>,
+    pruned_at: u128 // when datagrams that haven't come up in a while were last forgotten
+}
+
+/* FaultInjector decides which datagrams are affected by a fault
+   every decision is drawn from the seed along with the client, the key of the datagram and how many times it has come up before,
+   so a datagram that is sent again, like a retransmitted request or its reply, gets faults of its own */
+pub(crate) struct FaultInjector {
+    policy: FaultPolicy,
+    attempts: Mutex<Attempts>
+}
+
+impl FaultInjector {
+    // datagrams that haven't come up for this long are forgotten, which only ever matters to a client retransmitting that slowly
+    const RETENTION: u128 = 60000;
+
+    pub(crate) fn new(policy: FaultPolicy) -> FaultInjector {
+        return FaultInjector{policy, attempts: Mutex::new(Attempts{seen: HashMap::new(), pruned_at: get_time()})};
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        return self.policy.is_active();
+    }
+
+    // the sequence that the faults of a datagram are drawn from, which is counted as another attempt of it
+    fn rng(&self, addr: SocketAddr, key: DatagramKey) -> Rng {
+        let time = get_time();
+        let mut attempts = self.attempts.lock().unwrap();
+        if time > attempts.pruned_at + FaultInjector::RETENTION {
+            attempts.pruned_at = time;
+            attempts.seen.retain(|_, (_, seen_at)| time <= *seen_at + FaultInjector::RETENTION);
+        }
+        let (attempt, seen_at) = attempts.seen.entry((addr, key)).or_insert((0, time));
+        *attempt += 1;
+        *seen_at = time;
+
+        let mut hash = mix(self.policy.seed, key.0);
+        hash = match addr.ip() {
+            IpAddr::V4(ip) => mix(hash, u32::from(ip) as u64),
+            IpAddr::V6(ip) => mix(mix(hash, (u128::from(ip) >> 64) as u64), u128::from(ip) as u64),
+        };
+        hash = mix(hash, addr.port() as u64);
+        return Rng::new(mix(hash, *attempt));
+    }
+
+    // whether a datagram received from a client should be dropped
+    pub(crate) fn drop_request(&self, addr: SocketAddr, buf: &[u8]) -> bool {
+        if self.policy.drop_requests <= 0.0 {
+            return false;
+        }
+        return self.rng(addr, DatagramKey::request(buf)).chance(self.policy.drop_requests);
+    }
+
+    /* the copies of a datagram to a client that should be sent, each with how long it is held back for
+       no copies means the datagram is dropped */
+    pub(crate) fn reply_copies(&self, addr: SocketAddr, key: DatagramKey) -> Vec<Option<Duration>> {
+        if self.policy.drop_replies <= 0.0 && self.policy.duplicate_replies <= 0.0 && self.policy.delay_replies <= 0.0 {
+            return vec![None];
+        }
+        let mut rng = self.rng(addr, key);
+        if rng.chance(self.policy.drop_replies) {
+            return Vec::new();
+        }
+        let count = if rng.chance(self.policy.duplicate_replies) { 2 } else { 1 };
+        let mut copies: Vec<Option<Duration>> = Vec::new();
+        for _ in 0..count {
+            // every copy is delayed on its own, so a duplicate can overtake the original
+            let mut delay: Option<Duration> = None;
+            if rng.chance(self.policy.delay_replies) {
+                delay = Some(self.policy.max_delay.mul_f64(rng.next_f64()));
+            }
+            copies.push(delay);
+        }
+        return copies;
+    }
+}
+
+// A datagram held back by a delay fault
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct DelayedDatagram {
+    due: Instant,
+    seq: u64, // datagrams that are due at the same time are sent in the order they were delayed
+    buf: Vec<u8>,
+    addr: SocketAddr
+}
+
+// DelayQueue keeps delayed datagrams until they are due, earliest first
+pub(crate) struct DelayQueue {
+    queue: Mutex<(u64, BinaryHeap<Reverse<DelayedDatagram>>)>, // the sequence number of the next datagram, and the datagrams themselves
+    ready: Condvar,
+    closed: AtomicBool // set once the server stops, after which nothing more is taken out
+}
+
+impl DelayQueue {
+    pub(crate) fn new() -> DelayQueue {
+        return DelayQueue{queue: Mutex::new((0, BinaryHeap::new())), ready: Condvar::new(), closed: AtomicBool::new(false)};
+    }
+
+    // wakes up whoever is waiting on the queue, and drops the datagrams that are still held back
+    pub(crate) fn close(&self) {
+        let _queue = self.queue.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        self.ready.notify_all();
+    }
+
+    pub(crate) fn push(&self, delay: Duration, buf: Vec<u8>, addr: SocketAddr) {
+        let mut queue = self.queue.lock().unwrap();
+        let seq = queue.0;
+        queue.0 += 1;
+        queue.1.push(Reverse(DelayedDatagram{due: Instant::now() + delay, seq, buf, addr}));
+        // the new datagram may be due before the one that is being waited for
+        self.ready.notify_one();
+    }
+
+    // waits until the earliest datagram is due and takes it out, None once the queue has been closed
+    pub(crate) fn pop_due(&self) -> Option<(Vec<u8>, SocketAddr)> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            let wait = match queue.1.peek() {
+                Some(Reverse(datagram)) => datagram.due.saturating_duration_since(Instant::now()),
+                None => {
+                    queue = self.ready.wait(queue).unwrap();
+                    continue;
+                }
+            };
+            if wait.is_zero() {
+                let Reverse(datagram) = queue.1.pop().unwrap();
+                return Some((datagram.buf, datagram.addr));
+            }
+            queue = self.ready.wait_timeout(queue, wait).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn injector(seed: u64) -> FaultInjector {
+        return FaultInjector::new(FaultPolicy{drop_requests: 0.5, drop_replies: 0.3, duplicate_replies: 0.3, delay_replies: 0.3, max_delay: Duration::from_millis(1000), seed});
+    }
+
+    // whether a request was dropped, and the copies of its reply
+    type Faults = (bool, Vec<Option<Duration>>);
+
+    // the faults of every request and its reply, each sent a few times
+    fn faults(injector: &FaultInjector, order: &[u32]) -> HashMap<u32, Vec<Faults>> {
+        let addr: SocketAddr = "127.0.0.1:45601".parse().unwrap();
+        let mut faults: HashMap<u32, Vec<Faults>> = HashMap::new();
+        for req_no in order {
+            let request = [req_no.to_be_bytes().as_slice(), &[0, 0, 0, 0, 2]].concat();
+            let dropped = injector.drop_request(addr, &request);
+            let copies = injector.reply_copies(addr, DatagramKey::reply(&request));
+            faults.entry(*req_no).or_default().push((dropped, copies));
+        }
+        return faults;
+    }
+
+    #[test]
+    fn faults_only_depend_on_the_datagrams() {
+        let in_order: Vec<u32> = (0..50).flat_map(|req_no| [req_no; 3]).collect();
+        let shuffled: Vec<u32> = (0..3).flat_map(|_| (0..50).rev()).collect();
+        assert_eq!(faults(&injector(42), &in_order), faults(&injector(42), &shuffled));
+        assert_ne!(faults(&injector(42), &in_order), faults(&injector(43), &in_order));
+    }
+
+    #[test]
+    fn sending_again_gets_faults_of_its_own() {
+        let attempts: Vec<u32> = vec![7; 20];
+        let faults = faults(&injector(42), &attempts);
+        assert!(faults[&7].iter().any(|(dropped, _)| *dropped));
+        assert!(faults[&7].iter().any(|(dropped, _)| !*dropped));
+    }
+}
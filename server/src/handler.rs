@@ -0,0 +1,885 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::{Arc, Mutex, RwLock};
+use inline_colorization::*;
+
+use crate::fault::DatagramKey;
+use crate::get_time;
+use crate::history::ResponseManager;
+use crate::monitor::{MonitorManager, MonitorOptions, MonitorRange, NotifyPolicy};
+use crate::protocol::{CreateFlag, DecodeError, Delta, EntryType, MonitorFlag, RequestHandler, RequestOperation, ResponseMarshal, StatusCode};
+use crate::storage::{EntryInfo, PathError, Storage};
+use crate::transport::{Peer, Transport};
+
+/* ModificationTracker keeps a track of when each file was last modified through the server
+   so that clients can check if their cached copy is still valid without reading it again.
+   What the storage reported for a file after its last change is kept as well, which tells when it has been changed outside the server */
+pub(crate) struct ModificationTracker {
+    times: HashMap<PathBuf, u64>, // map file to the epoch milliseconds of its last modification
+    observed: HashMap<PathBuf, (u64, u64)> // map file to the modification time and size the storage last reported for it
+}
+
+impl ModificationTracker {
+    fn new() -> ModificationTracker {
+        return ModificationTracker{times: HashMap::new(), observed: HashMap::new()};
+    }
+
+    /* returns the last modification time of a file given what the storage reports for it, which is used as is if the server hasn't modified it yet
+       a change made outside the server since then is counted before it has been recorded, and gets the same time either way */
+    fn time_of(&self, file: &Path, info: &EntryInfo) -> u64 {
+        let time = match self.times.get(file) {
+            Some(time) => *time,
+            None => return info.modified,
+        };
+        if self.observed.get(file).is_some_and(|observed| *observed != (info.modified, info.size)) {
+            return info.modified.max(time + 1);
+        }
+        return time;
+    }
+
+    // returns the last modification time of a file, which is looked up in the storage
+    fn get_time(&self, file: &Path, storage: &dyn Storage) -> u64 {
+        return match storage.stat(file) {
+            Ok(info) => self.time_of(file, &info),
+            Err(_) => self.times.get(file).copied().unwrap_or(0),
+        };
+    }
+
+    // records a modification of a file and returns the new modification time
+    fn touch(&mut self, file: PathBuf, storage: &dyn Storage) -> u64 {
+        // two modifications within the same millisecond must still get different times
+        let previous = self.get_time(&file, storage);
+        let time = (get_time() as u64).max(previous + 1);
+        let file_str = file.to_string_lossy();
+        println!("{style_bold}{color_red}[ModificationTracker]:{style_reset} {file_str} was modified at {time}");
+        if let Ok(info) = storage.stat(&file) {
+            self.observed.insert(file.clone(), (info.modified, info.size));
+        }
+        self.times.insert(file, time);
+        return time;
+    }
+
+    /* records a change made to a file outside the server, returning the modification time it is given
+       None if it hasn't changed since the storage last reported on it, or if it hasn't been observed at all */
+    pub(crate) fn refresh(&mut self, file: &Path, storage: &dyn Storage) -> Option<u64> {
+        let info = storage.stat(file).ok()?;
+        if *self.observed.get(file)? == (info.modified, info.size) {
+            return None;
+        }
+        let time = self.time_of(file, &info);
+        let file_str = file.to_string_lossy();
+        println!("{style_bold}{color_red}[ModificationTracker]:{style_reset} {file_str} was modified outside the server, at {time}");
+        self.times.insert(file.to_path_buf(), time);
+        self.observed.insert(file.to_path_buf(), (info.modified, info.size));
+        return Some(time);
+    }
+
+    // remembers what the storage reports for a file that hasn't been observed yet, returning whether it is new
+    pub(crate) fn observe(&mut self, file: &Path, info: &EntryInfo) -> bool {
+        if self.observed.contains_key(file) {
+            return false;
+        }
+        self.observed.insert(file.to_path_buf(), (info.modified, info.size));
+        return true;
+    }
+
+    // the observed files in a directory, including those in the directories under it if recursive
+    pub(crate) fn observed_in(&self, dir: &Path, recursive: bool) -> Vec<PathBuf> {
+        return self.observed.keys().filter(|file| if recursive { file.starts_with(dir) && *file != dir } else { file.parent() == Some(dir) }).cloned().collect();
+    }
+
+    /* forgets what was observed of files that are no longer monitored, on their own or through a directory they are in
+       files modified through the server are kept, since their modification time is kept for them anyway */
+    pub(crate) fn retain_watched(&mut self, watched: &[(PathBuf, bool)]) {
+        let times = &self.times;
+        self.observed.retain(|file, _| times.contains_key(file) || watched.iter().any(|(path, recursive)| {
+            return file == path || if *recursive { file.starts_with(path) } else { file.parent() == Some(path.as_path()) };
+        }));
+    }
+
+    // stops tracking a file that no longer exists, returning whether it had been observed
+    pub(crate) fn forget(&mut self, file: &Path) -> bool {
+        self.times.remove(file);
+        return self.observed.remove(file).is_some();
+    }
+}
+
+/* FileLocks hands out a reader/writer lock for each path, so that requests on different files can be serviced at the same time
+   while changes to the same file happen one after another */
+pub(crate) struct FileLocks {
+    pub(crate) locks: Mutex<HashMap<PathBuf, Arc<RwLock<()>>>>
+}
+
+impl FileLocks {
+    // returns the lock of a path, creating it if no other request is using the path
+    pub(crate) fn get(&self, file: &Path) -> FileLock<'_> {
+        let lock = self.locks.lock().unwrap().entry(file.to_path_buf()).or_default().clone();
+        return FileLock{locks: self, file: file.to_path_buf(), lock};
+    }
+}
+
+/* the lock of a path handed out by FileLocks. the last one to let go of it drops it from the map,
+   so the map only grows with the number of busy paths */
+pub(crate) struct FileLock<'a> {
+    locks: &'a FileLocks,
+    file: PathBuf,
+    lock: Arc<RwLock<()>>
+}
+
+impl Deref for FileLock<'_> {
+    type Target = RwLock<()>;
+
+    fn deref(&self) -> &RwLock<()> {
+        return &self.lock;
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        // the lock is only handed out while the map is locked, so nobody can pick it up in between
+        let mut locks = self.locks.locks.lock().unwrap();
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.file);
+        }
+    }
+}
+
+/* everything the worker threads share while servicing requests
+   each manager has its own lock, which is only held for as long as it is being used */
+pub struct ServerState<'a> {
+    pub(crate) storage: Box<dyn Storage>, // where the files clients operate on are kept
+    pub(crate) at_most_once: bool,
+    pub(crate) transport: &'a Transport<'a>,
+    pub(crate) monitor_manager: Mutex<MonitorManager>,
+    pub(crate) modification_tracker: Mutex<ModificationTracker>,
+    pub(crate) response_manager: Mutex<ResponseManager>,
+    pub(crate) file_locks: FileLocks
+}
+
+impl<'a> ServerState<'a> {
+    pub fn new(storage: Box<dyn Storage>, transport: &'a Transport<'a>, response_manager: ResponseManager, notify_policy: NotifyPolicy, at_most_once: bool) -> ServerState<'a> {
+        return ServerState{
+            storage,
+            at_most_once,
+            transport,
+            monitor_manager: Mutex::new(MonitorManager::new(notify_policy)),
+            modification_tracker: Mutex::new(ModificationTracker::new()),
+            response_manager: Mutex::new(response_manager),
+            file_locks: FileLocks{locks: Mutex::new(HashMap::new())}
+        };
+    }
+
+    /* informs the monitors of a file that has been changed outside the server since it was last looked at, returning whether it had
+       the caller holds the lock of the file, so the update goes out before anything that follows it */
+    pub(crate) fn catch_up(&self, file: &Path) -> bool {
+        let mtime = match self.modification_tracker.lock().unwrap().refresh(file, self.storage.as_ref()) {
+            Some(mtime) => mtime,
+            None => return false,
+        };
+        let outbox = self.monitor_manager.lock().unwrap().inform_monitors(file.to_path_buf(), self.storage.as_ref(), mtime, None);
+        outbox.send(self.transport);
+        return true;
+    }
+
+    // drops the monitors and the saved responses of a client that is gone, such as one whose connection has been closed
+    pub(crate) fn forget_client(&self, addr: Peer) {
+        self.response_manager.lock().unwrap().forget_client(&addr);
+        self.monitor_manager.lock().unwrap().forget_client(addr);
+    }
+}
+
+impl<'a> RequestHandler<'a> {
+    // whether an operation changes a file, rather than only looking at it
+    fn modifies(op: u8) -> bool {
+        return op == RequestOperation::APPEND || op == RequestOperation::INSERT || op == RequestOperation::UPDATE || op == RequestOperation::DELETE ||
+            op == RequestOperation::CREATE || op == RequestOperation::REMOVE;
+    }
+
+    // creates a file with some initial content, replacing any existing file unless the client asks not to
+    fn create_file(&mut self, path: PathBuf, state: &ServerState) -> Result<ResponseMarshal, DecodeError> {
+        // create has a flags byte followed by the initial content, which may be empty
+        let flags: u8 = self.read_byte()?;
+        let content: &[u8] = self.read_bytes()?;
+        self.finish()?;
+        let fail_if_exists = flags & CreateFlag::FAIL_IF_EXISTS != 0;
+        let path_str = path.to_string_lossy();
+        let content_len = content.len();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to create {path_str} with {content_len} bytes (fail if exists: {fail_if_exists})");
+
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"File Created".to_vec()};
+        let existing = state.storage.stat(&path);
+        if existing.as_ref().is_ok_and(|info| info.entry_type == EntryType::DIRECTORY) {
+            response.status = StatusCode::ALREADY_EXISTS;
+            response.data = b"A directory already exists at this path".to_vec();
+            return Ok(response);
+        }
+
+        // without replacing, the existence check and the creation are a single step
+        let result = state.storage.write(&path, content, !fail_if_exists);
+        if result.is_err() {
+            let err = result.unwrap_err();
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when creating file {path_str}");
+            response.status = StatusCode::from_io_error(&err);
+            response.data = b"Could not create file".to_vec();
+            return Ok(response);
+        }
+
+        // an existing file may have been replaced, so clients monitoring it are informed as well
+        let mtime = state.modification_tracker.lock().unwrap().touch(path.clone(), state.storage.as_ref());
+        let outbox = if existing.is_ok() {
+            state.monitor_manager.lock().unwrap().inform_monitors(path, state.storage.as_ref(), mtime, None)
+        } else {
+            state.monitor_manager.lock().unwrap().inform_created(&path)
+        };
+        outbox.send(state.transport);
+        return Ok(response);
+    }
+
+    // removes a file and lets any client monitoring it know
+    fn remove_file(&mut self, path: PathBuf, state: &ServerState) -> Result<ResponseMarshal, DecodeError> {
+        self.finish()?;
+        let path_str = path.to_string_lossy();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to remove {path_str}");
+
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"File Removed".to_vec()};
+        // directories can't be removed through this operation
+        if !state.storage.stat(&path).is_ok_and(|info| info.entry_type == EntryType::FILE) {
+            response.status = StatusCode::NOT_FOUND;
+            response.data = b"Invalid File Path".to_vec();
+            return Ok(response);
+        }
+
+        let result = state.storage.remove(&path);
+        if result.is_err() {
+            let err = result.unwrap_err();
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when removing file {path_str}");
+            response.status = StatusCode::from_io_error(&err);
+            response.data = b"Could not remove file".to_vec();
+            return Ok(response);
+        }
+
+        state.modification_tracker.lock().unwrap().forget(&path);
+        let outbox = state.monitor_manager.lock().unwrap().inform_removed(&path);
+        outbox.send(state.transport);
+        return Ok(response);
+    }
+
+    // lists the entries of a directory, each marshalled as a length-prefixed name followed by its type, size and modification time
+    fn list_directory(&self, path: PathBuf, state: &ServerState) -> ResponseMarshal {
+        let path_str = path.to_string_lossy();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to list the entries of {path_str}");
+
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: Vec::new()};
+        // a file can't be listed, which the client is told apart from a path that doesn't exist
+        if state.storage.stat(&path).is_ok_and(|info| info.entry_type == EntryType::FILE) {
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {path_str} is a file, not a directory");
+            response.status = StatusCode::NOT_A_DIRECTORY;
+            response.data = b"Path is a file, not a directory".to_vec();
+            return response;
+        }
+        let list = match state.storage.list(&path) {
+            Ok(list) => list,
+            Err(err) => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when listing {path_str}");
+                response.status = StatusCode::from_io_error(&err);
+                response.data = b"Invalid Directory Path".to_vec();
+                return response;
+            }
+        };
+
+        let mut entries: Vec<(String, u8, u64, u64)> = Vec::new();
+        let modification_tracker = state.modification_tracker.lock().unwrap();
+        for (name, entry_path, info) in list {
+            // the storage already knows when the entry was modified, which is only overridden by the server's own record
+            let mtime = modification_tracker.time_of(&entry_path, &info);
+            entries.push((name, info.entry_type, info.size, mtime));
+        }
+        drop(modification_tracker);
+        // sorted by name so that the listing is the same every time
+        entries.sort();
+
+        let count = entries.len();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {path_str} has {count} entries");
+        response.data.extend((count as u32).to_be_bytes());
+        for (name, entry_type, size, mtime) in entries {
+            response.data.extend((name.len() as u32).to_be_bytes());
+            response.data.extend(name.as_bytes());
+            response.data.push(entry_type);
+            response.data.extend(size.to_be_bytes());
+            response.data.extend(mtime.to_be_bytes());
+        }
+        return response;
+    }
+
+    // sends back the type, size, modification time and permission bits of a file or directory
+    fn stat_path(&self, path: PathBuf, state: &ServerState) -> ResponseMarshal {
+        let path_str = path.to_string_lossy();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to know about {path_str}");
+
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: Vec::new()};
+        let info = match state.storage.stat(&path) {
+            Ok(info) => info,
+            Err(err) => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when reading metadata of {path_str}");
+                response.status = StatusCode::from_io_error(&err);
+                response.data = b"Could not read metadata".to_vec();
+                return response;
+            }
+        };
+
+        let mtime = state.modification_tracker.lock().unwrap().time_of(&path, &info);
+        response.data.push(info.entry_type);
+        response.data.extend(info.size.to_be_bytes());
+        response.data.extend(mtime.to_be_bytes());
+        response.data.extend(info.permissions.to_be_bytes());
+        return response;
+    }
+
+    /* starts a monitor on a directory, which reports on every file in it that is created, changed or removed
+       the flags are optional just like for a file, while a range can't be given */
+    fn monitor_directory(&mut self, path: PathBuf, file_path: &str, state: &ServerState, addr: Peer) -> Result<ResponseMarshal, DecodeError> {
+        let path_str = path.to_string_lossy();
+        let interval: u32 = self.read_int()?;
+        let flags: u8 = if self.remaining() > 0 { self.read_byte()? } else { 0 };
+        if self.remaining() > 0 {
+            return Ok(ResponseMarshal{status: StatusCode::UNSUPPORTED, timestamp: None, data: b"Only files can be monitored in part".to_vec()});
+        }
+        let recursive = flags & MonitorFlag::RECURSIVE != 0;
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client has requested to monitor directory {path_str} for {interval}ms (recursive: {recursive})");
+        let options = MonitorOptions{recursive, ..MonitorOptions::default()};
+        state.monitor_manager.lock().unwrap().add_interval(path, file_path.to_string(), addr, interval, options);
+        return Ok(ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"Operation Completed".to_vec()});
+    }
+
+    // cancels the monitor the client has on a file before its interval runs out
+    fn cancel_monitor(&self, path: PathBuf, state: &ServerState, addr: Peer) -> ResponseMarshal {
+        let path_str = path.to_string_lossy();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to stop monitoring {path_str}");
+        if state.monitor_manager.lock().unwrap().remove_interval(&path, addr) {
+            return ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"Monitor Cancelled".to_vec()};
+        }
+        return ResponseMarshal{status: StatusCode::NOT_FOUND, timestamp: None, data: b"File is not being monitored".to_vec()};
+    }
+
+    /* sends the whole file again to a client that missed an update, with the sequence number of the latest update before the content
+       only the range is sent for a monitor on part of the file */
+    fn resync_monitor(&self, id: u32, state: &ServerState, addr: Peer) -> ResponseMarshal {
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to resync monitor {id}");
+        let not_found = ResponseMarshal{status: StatusCode::NOT_FOUND, timestamp: None, data: b"Monitor does not exist".to_vec()};
+        let path = match state.monitor_manager.lock().unwrap().find_monitor(addr, id) {
+            Some((path, _, _)) => path,
+            None => return not_found,
+        };
+        // every update of a directory monitor stands on its own, so there is nothing to catch up on
+        if state.storage.stat(&path).is_ok_and(|info| info.entry_type == EntryType::DIRECTORY) {
+            return ResponseMarshal{status: StatusCode::UNSUPPORTED, timestamp: None, data: b"Directory monitors can't be resynced".to_vec()};
+        }
+
+        // updates are sent while the file is locked for the change, so locking it here keeps the content in step with the sequence number
+        let lock = state.file_locks.get(&path);
+        let _guard = lock.read().unwrap();
+        let (seq, range) = match state.monitor_manager.lock().unwrap().find_monitor(addr, id) {
+            Some((_, seq, range)) => (seq, range),
+            None => return not_found,
+        };
+        let content = match state.storage.read_all(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                let path_str = path.to_string_lossy();
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when reading file {path_str}");
+                return ResponseMarshal{status: StatusCode::from_io_error(&err), timestamp: None, data: b"Could not read file".to_vec()};
+            }
+        };
+        let mtime = state.modification_tracker.lock().unwrap().get_time(&path, state.storage.as_ref());
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: Some(mtime), data: seq.to_be_bytes().to_vec()};
+        match range {
+            Some(range) => response.data.extend(range.slice(&content)),
+            None => response.data.extend(content),
+        }
+        return response;
+    }
+
+    // sends back every monitor the client has, each marshalled as the length-prefixed path it gave followed by the milliseconds left
+    fn list_monitors(&self, state: &ServerState, addr: Peer) -> ResponseMarshal {
+        let list = state.monitor_manager.lock().unwrap().list_intervals(addr);
+        let count = list.len();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client has {count} monitors");
+        let mut response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: Vec::new()};
+        response.data.extend((count as u32).to_be_bytes());
+        for (name, remaining) in list {
+            response.data.extend((name.len() as u32).to_be_bytes());
+            response.data.extend(name.as_bytes());
+            response.data.extend((remaining as u32).to_be_bytes());
+        }
+        return response;
+    }
+
+    // this function handles user services (read, insert, delete, update, monitor, validate, create, remove, list, stat, append, unmonitor) if there is no saved response
+    fn parse_request(&mut self, op: u8, state: &ServerState, addr: Peer) -> Result<ResponseMarshal, DecodeError> {
+        let mut response =  ResponseMarshal{status: StatusCode::BAD, timestamp: None, data: b"Operation Completed".to_vec()};
+
+        // just in case an incorrect service request is received
+        match op {
+            RequestOperation::READ |  RequestOperation::INSERT | RequestOperation::DELETE | RequestOperation::UPDATE | RequestOperation::MONITOR | RequestOperation::VALIDATE |
+            RequestOperation::CREATE | RequestOperation::REMOVE | RequestOperation::LIST | RequestOperation::STAT | RequestOperation::APPEND |
+            RequestOperation::UNMONITOR => {},
+            _ => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client requested for an invalid operation");
+                response.status = StatusCode::UNSUPPORTED;
+                response.data = b"Invalid Operation".to_vec();
+                return Ok(response);
+            },
+        }
+
+        // all services have a file path at the start, which unlike the data must be text
+        let file_path: &str = match str::from_utf8(self.read_bytes()?) {
+            Ok(file_path) => file_path,
+            Err(_) => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} file path is not valid UTF-8");
+                response.status = StatusCode::MALFORMED;
+                response.data = b"Invalid File Path".to_vec();
+                return Ok(response);
+            }
+        };
+        
+        let path: PathBuf = match state.storage.resolve(file_path, op == RequestOperation::CREATE) {
+            Ok(path) => path,
+            Err(PathError::OutsideRoot) => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {file_path} is outside the server file directory");
+                response.status = StatusCode::OUTSIDE_ROOT;
+                response.data = b"File Path is outside the server directory".to_vec();
+                return Ok(response);
+            },
+            Err(PathError::Unresolvable(err)) => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when resolving {file_path}");
+                response.status = StatusCode::from_io_error(&err);
+                response.data = b"Invalid File Path".to_vec();
+                return Ok(response);
+            }
+        };
+        let path_str = path.to_string_lossy();
+        println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {path_str} is the file to operate on");
+
+        /* the path stays locked for the rest of the request. changes hold the lock on their own, which keeps them in order
+           along with the monitor updates they send out, while anything that only looks at the path can share it */
+        let lock = state.file_locks.get(&path);
+        if RequestHandler::modifies(op) {
+            let _guard = lock.write().unwrap();
+            // monitors that are sent deltas need to hear about changes made outside the server before the one they apply to
+            state.catch_up(&path);
+            return self.service_request(op, path, file_path, state, addr, response);
+        }
+        let _guard = lock.read().unwrap();
+        return self.service_request(op, path, file_path, state, addr, response);
+    }
+
+    // services a request on a path, which has already been resolved and locked. the path as the client gave it is kept for monitors
+    fn service_request(&mut self, op: u8, path: PathBuf, file_path: &str, state: &ServerState, addr: Peer, mut response: ResponseMarshal) -> Result<ResponseMarshal, DecodeError> {
+        let path_str = path.to_string_lossy();
+
+        // create, remove, list, stat, unmonitor and monitors on a directory don't need an existing file, so they are handled separately
+        if op == RequestOperation::CREATE {
+            return self.create_file(path, state);
+        } else if op == RequestOperation::REMOVE {
+            return self.remove_file(path, state);
+        } else if op == RequestOperation::LIST {
+            self.finish()?;
+            return Ok(self.list_directory(path, state));
+        } else if op == RequestOperation::STAT {
+            self.finish()?;
+            return Ok(self.stat_path(path, state));
+        } else if op == RequestOperation::UNMONITOR {
+            self.finish()?;
+            return Ok(self.cancel_monitor(path, state, addr));
+        } else if op == RequestOperation::MONITOR && state.storage.stat(&path).is_ok_and(|info| info.entry_type == EntryType::DIRECTORY) {
+            return self.monitor_directory(path, file_path, state, addr);
+        }
+
+        // check if the path is a file
+        let len: u64 = match state.storage.stat(&path) {
+            Ok(info) if info.entry_type == EntryType::FILE => info.size,
+            _ => {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {path_str} is not a file");
+                response.status = StatusCode::NOT_FOUND;
+                response.data = b"Invalid File Path".to_vec();
+                return Ok(response);
+            }
+        };
+        let mut offset: u32 = 0;
+
+        // all requests except monitor and append have an offset included next
+        if op != RequestOperation::MONITOR && op != RequestOperation::APPEND {
+            offset = self.read_int()?;
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {offset} is the offset to seek to");
+            /* offset should not go beyond the length of the file
+               it can be equal to it though, which allows inserting at the end and zero-length reads */
+            if offset as u64 > len {
+                response.status = StatusCode::OUT_OF_RANGE;
+                response.data = b"Offset is too large".to_vec();
+                return Ok(response);
+            }
+        }
+
+        // the result of a change is only known once it has been handed to the storage, while what changed is described for monitors
+        let mut written: Option<Result<(), Error>> = None;
+        let mut change: Option<Delta> = None;
+
+        if op == RequestOperation::APPEND {
+            // append only contains data, which always goes at the end of the file as it is when the request is handled
+            let data: &[u8] = self.read_bytes()?;
+            self.finish()?;
+            let data_str = String::from_utf8_lossy(data);
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to append '{data_str}' at the end ({len})");
+            // the offset the data ended up at is sent back, since the client may not have known the length
+            written = Some(state.storage.append(&path, data).map(|at| {
+                response.data = at.to_be_bytes().to_vec();
+                change = Some(Delta{op, offset: at, removed: 0, data: data.to_vec(), length: at + data.len() as u64});
+            }));
+        } else if op == RequestOperation::INSERT || op == RequestOperation::UPDATE {
+            // insert and update both contain data, which is only interpreted as text for logging
+            let data: &[u8] = self.read_bytes()?;
+            self.finish()?;
+            let data_str = String::from_utf8_lossy(data);
+            let data_len = data.len();
+            if op == RequestOperation::UPDATE  {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to overwrite '{data_str}' starting from the offset");
+                // if the given string extends beyond the length of the file, it is treated as an error. no insertion will happen
+                if offset as u64 + data_len as u64 > len {
+                    println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {offset} + length of data ({data_len}) exceeds the file size ({len})");
+                    response.status = StatusCode::OUT_OF_RANGE;
+                    response.data = b"Offset+Data is too large".to_vec();
+                    return Ok(response);
+                }
+                // overwrite the data starting from the offset
+                written = Some(state.storage.update(&path, offset as u64, data));
+                change = Some(Delta{op, offset: offset as u64, removed: data_len as u64, data: data.to_vec(), length: len});
+            } else {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to insert '{data_str}' at the offset");
+                // the data goes in at the offset, essentially shifting everything after it to the right
+                written = Some(state.storage.insert(&path, offset as u64, data));
+                change = Some(Delta{op, offset: offset as u64, removed: 0, data: data.to_vec(), length: len + data_len as u64});
+            }
+        } else if op == RequestOperation::READ || op == RequestOperation::VALIDATE || op == RequestOperation::DELETE {
+            // read, validate and delete all contain an integer amount
+            let amount: u32 = self.read_int()?;
+            // validate additionally contains the modification time of the client's cached copy
+            let client_time: Option<u64> = if op == RequestOperation::VALIDATE { Some(self.read_long()?) } else { None };
+            self.finish()?;
+            // if the given offset + amount extends beyond the length of the file, it is treated as an error. 
+            if offset as u64 + amount as u64 > len {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {offset} + {amount} exceeds the file size ({len})");
+                response.status = StatusCode::OUT_OF_RANGE;
+                response.data = b"Offset+Amount is too large".to_vec();
+                return Ok(response);
+            } 
+            if op == RequestOperation::READ || op == RequestOperation::VALIDATE {
+                let mtime = state.modification_tracker.lock().unwrap().get_time(&path, state.storage.as_ref());
+                // content is always sent along with the modification time it corresponds to
+                response.timestamp = Some(mtime);
+                if let Some(client_time) = client_time {
+                    println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client has a cached copy from {client_time}, file was last modified at {mtime}");
+                    if client_time == mtime {
+                        // the file hasn't changed so the client can simply extend the validity of its copy
+                        response.status = StatusCode::UNCHANGED;
+                        response.data = b"Not Modified".to_vec();
+                        return Ok(response);
+                    }
+                }
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to read {amount} bytes starting from the offset");
+                let result = state.storage.read(&path, offset as u64, amount as u64);
+                if result.is_err() {
+                    let err = result.unwrap_err();
+                    println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when reading file {path_str}");
+                    response.timestamp = None;
+                    response.status = StatusCode::from_io_error(&err);
+                    response.data = b"Could not read file".to_vec();
+                    return Ok(response);
+                }
+                // the raw bytes serve as the response to the client
+                response.data = result.unwrap();
+            } else {
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to delete {amount} bytes starting from the offset");
+                // the bytes are taken out, essentially shifting everything after them to the left
+                written = Some(state.storage.delete(&path, offset as u64, amount as u64));
+                change = Some(Delta{op, offset: offset as u64, removed: amount as u64, data: Vec::new(), length: len - amount as u64});
+            }
+        } else {
+            // offload monitor requests to the monitor manager
+            let interval: u32 = self.read_int()?;
+            // the flags are optional, so that clients that don't know about them still get the whole file with every change
+            let flags: u8 = if self.remaining() > 0 { self.read_byte()? } else { 0 };
+            let delta = flags & MonitorFlag::DELTA != 0;
+            // so is a range after them, without which the whole file is monitored
+            let mut range: Option<MonitorRange> = None;
+            if self.remaining() > 0 {
+                let range_offset: u32 = self.read_int()?;
+                let range_length: u32 = self.read_int()?;
+                // the range has to be within the file, just like a read
+                if range_offset as u64 + range_length as u64 > len {
+                    println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {range_offset} + {range_length} exceeds the file size ({len})");
+                    response.status = StatusCode::OUT_OF_RANGE;
+                    response.data = b"Offset+Length is too large".to_vec();
+                    return Ok(response);
+                }
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client only wants to monitor {range_length} bytes starting from {range_offset}");
+                range = Some(MonitorRange{offset: range_offset as u64, length: range_length as u64});
+            }
+            self.finish()?;
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client has requested to monitor {path_str} for {interval}ms (deltas: {delta})");
+            let options = MonitorOptions{delta, range, recursive: false};
+            // what the file is like now is what a change made outside the server is told apart from
+            if let Ok(info) = state.storage.stat(&path) {
+                state.modification_tracker.lock().unwrap().observe(&path, &info);
+            }
+            state.monitor_manager.lock().unwrap().add_interval(path.clone(), file_path.to_string(), addr, interval, options)
+        }
+
+        if let Some(result) = written {
+            if result.is_err() {
+                let err = result.unwrap_err();
+                println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} {err} when writing file {path_str}");
+                response.status = StatusCode::from_io_error(&err);
+                response.data = b"Could not write file".to_vec();
+                return Ok(response);
+            }
+            // file has been changed, record the time and check if other clients need to be informed
+            let mtime = state.modification_tracker.lock().unwrap().touch(path.clone(), state.storage.as_ref());
+            let outbox = state.monitor_manager.lock().unwrap().inform_monitors(path, state.storage.as_ref(), mtime, change);
+            outbox.send(state.transport);
+        }
+
+        // no errors encountered
+        response.status = StatusCode::GOOD;
+        return Ok(response);
+    }
+
+    /* this function is called for every request that is received
+       it returns the response to send back, if there is one */
+    pub fn process_request(&mut self, state: &ServerState, addr: Peer) -> Result<Option<ResponseMarshal>, DecodeError> {
+        /* all requests have a request number, an acknowledgement and an operation type in a byte
+           the acknowledgement tells us that the client is done with every request numbered below it */
+        let req_no = self.read_int()?;
+        let ack = self.read_int()?;
+        let op: u8 = self.read_byte()?;
+
+        let mut response_manager = state.response_manager.lock().unwrap();
+        response_manager.expire_idle();
+        response_manager.touch_client(&addr);
+
+        // handle handshakes here
+        if op == RequestOperation::HANDSHAKE {
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client initiated handshake");
+            let client_time = self.read_int()?;
+            self.finish()?;
+            if response_manager.session_map.contains_key(&addr) {
+                // the client already exists in the session map
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Found key for {client_time}");
+                if response_manager.session_map.get(&addr).unwrap() != &client_time {
+                    /* if the client_time is not the same, perhaps the client exited without disconnecting last time
+                       which means the data associated with the client might not have been flushed */
+                    println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Clearing old responses from this client. Previous session was {client_time}");
+                    response_manager.flush_client(&addr);
+                    response_manager.start_session(addr, client_time);
+                }
+                // if the client_time is the same then do nothing, most likely a repeat request
+            } else {
+                // if the client doesnt exist in the session map then we add an entry
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Creating blank response map for this client. Session id is {client_time}");
+                response_manager.start_session(addr, client_time);
+            }
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Sending handshake confirmation");
+            return Ok(Some(ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"Handshake Completed. Welcome!".to_vec()}));
+        }
+
+        // handle disconnects
+        if op == RequestOperation::DISCONNECT {
+            self.finish()?;
+            // simply flush any saved client data and return a message
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to disconnect");
+            response_manager.forget_client(&addr);
+            // a client that is gone shouldn't be sent any more updates
+            drop(response_manager);
+            state.monitor_manager.lock().unwrap().forget_client(addr);
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Sending disconnect confirmation");
+            return Ok(Some(ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: b"Bye!".to_vec()}));
+        }
+
+        // handle requests for missing fragments, which are answered with the fragments themselves
+        if op == RequestOperation::RESEND {
+            // resend contains a message id followed by a count of sequence numbers
+            let id = self.read_int()?;
+            let count = self.read_int()?;
+            let mut seqs: Vec<u32> = Vec::new();
+            for _ in 0..count {
+                seqs.push(self.read_int()?);
+            }
+            self.finish()?;
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client is missing {count} fragments of message {id}");
+            if state.transport.resend(addr, id, &seqs) {
+                return Ok(None);
+            }
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} message {id} is no longer available");
+            return Ok(Some(ResponseMarshal{status: StatusCode::NOT_FOUND, timestamp: None, data: b"Message is no longer available".to_vec()}));
+        }
+
+        // acknowledgements of monitor updates are one-way, so there is nothing to send back
+        if op == RequestOperation::MONITOR_ACK {
+            let id = self.read_int()?;
+            let seq = self.read_int()?;
+            self.finish()?;
+            drop(response_manager);
+            state.monitor_manager.lock().unwrap().acknowledge(addr, id, seq);
+            return Ok(None);
+        }
+
+        // resyncing a monitor changes nothing either, so it is answered right away
+        if op == RequestOperation::RESYNC {
+            let id = self.read_int()?;
+            self.finish()?;
+            drop(response_manager);
+            return Ok(Some(self.resync_monitor(id, state, addr)));
+        }
+
+        // listing monitors has no file path and changes nothing, so it is answered right away like a handshake
+        if op == RequestOperation::LIST_MONITORS {
+            self.finish()?;
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} client wants to list its monitors");
+            drop(response_manager);
+            return Ok(Some(self.list_monitors(state, addr)));
+        }
+
+        if state.at_most_once {
+            // responses the client is done with can be dropped before looking for a duplicate
+            response_manager.acknowledge(&addr, ack);
+            // duplicate filtering for at-most-once semantics
+            if response_manager.has_entry(&addr, req_no) {
+                // return saved response if it exists
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Req No. {req_no} from client is a duplicate! Sending back saved response.");
+                return Ok(Some(response_manager.get_entry(&addr, req_no).clone()));
+            } else if response_manager.is_stale(&addr, req_no) {
+                // the client is already done with this request, so a late copy of it is refused rather than carried out a second time
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Req No. {req_no} from client is older than any saved response. Refusing it.");
+                return Ok(Some(ResponseMarshal{status: StatusCode::STALE, timestamp: None, data: b"Request is too old to be serviced".to_vec()}));
+            } else if response_manager.in_flight.contains_key(&(addr, req_no)) {
+                // the original is still being serviced by another worker, the client gets the saved response when it retries
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Req No. {req_no} from client is a duplicate of a request still being serviced. Ignoring it.");
+                return Ok(None);
+            } else {
+                println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} No saved response found for req no. {req_no} from client");
+                response_manager.begin_request(addr, req_no, RequestHandler::modifies(op));
+            }
+        }
+        let position = response_manager.log_position();
+        // other clients shouldn't have to wait while this request is serviced
+        drop(response_manager);
+        // the intent has to be on disk before the change it announces can be made
+        if let Some((sync, records)) = position {
+            sync.wait(records);
+        }
+
+        // call the parse request function to service user requests
+        let result = self.parse_request(op, state, addr);
+        if state.at_most_once {
+            // save response if using at-most-once semantics, unless the client started a new session in the meantime
+            let mut response_manager = state.response_manager.lock().unwrap();
+            if response_manager.in_flight.remove(&(addr, req_no)).is_some() && result.is_ok() {
+                response_manager.add_entry(&addr, req_no, result.as_ref().unwrap().clone());
+            }
+        }
+        let response = result?;
+        let data = String::from_utf8_lossy(&response.data);
+        let status = StatusCode::name(response.status);
+        println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Response has status code {status} with data '{data}'");
+
+        // return response to be sent back to the client
+        let response_data = String::from_utf8_lossy(&response.data);
+        println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Sending data '{response_data}'");
+        return Ok(Some(response));
+    }
+}
+
+// processes a single request and sends back a reply, this is what each worker does with the requests it picks up
+pub fn service_message(state: &ServerState, buf: &[u8], src: Peer) {
+    let mut handler : RequestHandler = RequestHandler::new(buf);
+    let result = handler.process_request(state, src);
+    // whatever the request wrote to the log has to be on disk before the client hears back, so it survives a restart
+    let position = state.response_manager.lock().unwrap().log_position();
+    if let Some((sync, records)) = position {
+        sync.wait(records);
+    }
+    match result {
+        Ok(Some(response)) => state.transport.send(&response, src, DatagramKey::reply(buf)),
+        Ok(None) => {},
+        Err(err) => {
+            // a truncated or otherwise malformed request is rejected instead of being guessed at
+            println!("{style_bold}{color_magenta}[RequestHandler]:{style_reset} Malformed request: {err}");
+            state.transport.send(&ResponseMarshal{status: StatusCode::MALFORMED, timestamp: None, data: b"Malformed Request".to_vec()}, src, DatagramKey::reply(buf));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn file_locks() -> FileLocks {
+        return FileLocks{locks: Mutex::new(HashMap::new())};
+    }
+
+    #[test]
+    fn file_locks_exclude_changes_to_the_same_path() {
+        let locks = file_locks();
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            let lock = locks.get(Path::new("a"));
+            let guard = lock.write().unwrap();
+            scope.spawn(|| {
+                let lock = locks.get(Path::new("a"));
+                let _guard = lock.write().unwrap();
+                sender.send("a").unwrap();
+            });
+            // a different path is locked on its own
+            let other = locks.get(Path::new("b"));
+            assert!(other.try_write().is_ok());
+            assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(guard);
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok("a"));
+        });
+    }
+
+    #[test]
+    fn observed_files_are_forgotten_once_unwatched() {
+        let mut tracker = ModificationTracker::new();
+        let info = EntryInfo{entry_type: EntryType::FILE, size: 1, modified: 1, permissions: 0o644};
+        for file in ["a.txt", "dir/b.txt", "dir/sub/c.txt", "other/d.txt"] {
+            tracker.observe(Path::new(file), &info);
+        }
+        tracker.times.insert(PathBuf::from("other/d.txt"), 1);
+        let watched = vec![(PathBuf::from("a.txt"), false), (PathBuf::from("dir"), false)];
+        tracker.retain_watched(&watched);
+        let mut observed: Vec<&PathBuf> = tracker.observed.keys().collect();
+        observed.sort();
+        assert_eq!(observed, vec![Path::new("a.txt"), Path::new("dir/b.txt"), Path::new("other/d.txt")]);
+        tracker.retain_watched(&[]);
+        assert_eq!(tracker.observed.keys().collect::<Vec<_>>(), vec![Path::new("other/d.txt")]);
+    }
+
+    #[test]
+    fn file_locks_are_dropped_once_released() {
+        let locks = file_locks();
+        let first = locks.get(Path::new("a"));
+        let second = locks.get(Path::new("a"));
+        assert!(Arc::ptr_eq(&first.lock, &second.lock));
+        drop(first);
+        assert_eq!(locks.locks.lock().unwrap().len(), 1);
+        drop(second);
+        assert!(locks.locks.lock().unwrap().is_empty());
+    }
+}
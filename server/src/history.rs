@@ -0,0 +1,666 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Error, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use inline_colorization::*;
+
+use crate::get_time;
+use crate::protocol::{Reader, ResponseMarshal, StatusCode};
+use crate::transport::Peer;
+
+// Limits on the responses kept for each client under at-most-once semantics
+pub struct RetentionPolicy {
+    pub window: u32, // only the responses to this many of the latest request numbers are kept
+    pub max_bytes: usize, // the oldest responses are dropped once a client's responses take up more than this
+    pub idle_timeout: u128 // clients that haven't sent anything for this many milliseconds are forgotten entirely
+}
+
+/* The responses saved for a single client
+   requests numbered below the floor have either been acknowledged or had their response dropped, so they are never carried out again */
+#[derive(Default)]
+struct ResponseHistory {
+    responses: BTreeMap<u32, ResponseMarshal>, // ordered by request number so that the oldest can be dropped first
+    bytes: usize, // total size of the saved responses
+    floor: u32
+}
+
+impl ResponseHistory {
+    // drops the oldest saved response, which raises the floor past it
+    fn pop_oldest(&mut self) -> Option<u32> {
+        let (req_no, response) = self.responses.pop_first()?;
+        self.bytes -= response.size();
+        self.floor = self.floor.max(req_no.saturating_add(1));
+        return Some(req_no);
+    }
+
+    // drops the oldest saved response unless it is the one that must be kept
+    fn pop_oldest_except(&mut self, keep: u32) -> Option<u32> {
+        if self.responses.first_key_value().is_some_and(|(req_no, _)| *req_no == keep) {
+            return None;
+        }
+        return self.pop_oldest();
+    }
+}
+
+// A change to the ResponseManager, as written to the response log
+pub(crate) enum LogRecord {
+    Session { addr: Peer, session_id: u32 },
+    Flush { addr: Peer },
+    Response { addr: Peer, req_no: u32, response: ResponseMarshal },
+    Acknowledge { addr: Peer, ack: u32 },
+    Intent { addr: Peer, req_no: u32 } // a request that changes a file is about to be carried out, and is followed by its Response once it has
+}
+
+impl LogRecord {
+    pub const SESSION: u8 = 0;
+    pub const FLUSH: u8 = 1;
+    pub const RESPONSE: u8 = 2;
+    pub const ACKNOWLEDGE: u8 = 3;
+    pub const INTENT: u8 = 4;
+
+    // converts the record into bytes, starting with its type and the client as a length-prefixed string
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        let (record_type, addr) = match self {
+            LogRecord::Session { addr, .. } => (LogRecord::SESSION, addr),
+            LogRecord::Flush { addr } => (LogRecord::FLUSH, addr),
+            LogRecord::Response { addr, .. } => (LogRecord::RESPONSE, addr),
+            LogRecord::Acknowledge { addr, .. } => (LogRecord::ACKNOWLEDGE, addr),
+            LogRecord::Intent { addr, .. } => (LogRecord::INTENT, addr),
+        };
+        buf.push(record_type);
+        let addr_str = addr.to_string();
+        buf.extend((addr_str.len() as u32).to_be_bytes());
+        buf.extend(addr_str.as_bytes());
+        match self {
+            LogRecord::Session { session_id, .. } => buf.extend(session_id.to_be_bytes()),
+            LogRecord::Flush { .. } => {},
+            LogRecord::Response { req_no, response, .. } => {
+                buf.extend(req_no.to_be_bytes());
+                buf.push(response.status);
+                // a flag byte tells whether there is a timestamp, since it is optional
+                buf.push(response.timestamp.is_some() as u8);
+                buf.extend(response.timestamp.unwrap_or(0).to_be_bytes());
+                buf.extend((response.data.len() as u32).to_be_bytes());
+                buf.extend(&response.data);
+            },
+            LogRecord::Acknowledge { ack, .. } => buf.extend(ack.to_be_bytes()),
+            LogRecord::Intent { req_no, .. } => buf.extend(req_no.to_be_bytes()),
+        }
+        return buf;
+    }
+
+    // converts bytes back into a record, using the same unmarshalling as requests
+    fn from_bytes(buf: &[u8]) -> Option<LogRecord> {
+        let mut reader = Reader::new(buf);
+        let record_type = reader.read_byte().ok()?;
+        let addr: Peer = str::from_utf8(reader.read_bytes().ok()?).ok()?.parse().ok()?;
+        let record = match record_type {
+            LogRecord::SESSION => LogRecord::Session{addr, session_id: reader.read_int().ok()?},
+            LogRecord::FLUSH => LogRecord::Flush{addr},
+            LogRecord::RESPONSE => {
+                let req_no = reader.read_int().ok()?;
+                let status = reader.read_byte().ok()?;
+                let has_timestamp = reader.read_byte().ok()? != 0;
+                let timestamp = reader.read_long().ok()?;
+                let data = reader.read_bytes().ok()?.to_vec();
+                LogRecord::Response{addr, req_no, response: ResponseMarshal{status, timestamp: has_timestamp.then_some(timestamp), data}}
+            },
+            LogRecord::ACKNOWLEDGE => LogRecord::Acknowledge{addr, ack: reader.read_int().ok()?},
+            LogRecord::INTENT => LogRecord::Intent{addr, req_no: reader.read_int().ok()?},
+            _ => return None,
+        };
+        return Some(record);
+    }
+}
+
+/* LogSync makes the records written to the log durable without holding the lock of the ResponseManager
+   every record is numbered as it is written, and a single sync covers every record written before it,
+   so workers waiting on their records at the same time share one sync instead of taking turns */
+pub(crate) struct LogSync {
+    file: Mutex<Arc<File>>, // the log file, which is replaced when the log is compacted
+    written: AtomicU64, // the number of records written so far
+    synced: Mutex<u64> // the number of records known to be on disk, which is held while syncing
+}
+
+impl LogSync {
+    // waits until the given number of records are on disk, syncing the log if nobody else has yet
+    pub(crate) fn wait(&self, records: u64) {
+        let mut synced = self.synced.lock().unwrap();
+        if *synced >= records {
+            return;
+        }
+        // everything written up to now goes along with the records that are being waited on
+        let target = self.written.load(Ordering::SeqCst);
+        let file = self.file.lock().unwrap().clone();
+        let result = file.sync_data();
+        if result.is_err() {
+            let err = result.unwrap_err();
+            println!("{style_bold}{color_blue}[ResponseLog]:{style_reset} Error syncing the log: {err}");
+            return;
+        }
+        *synced = target;
+    }
+}
+
+/* ResponseLog is an append-only file of LogRecords that is replayed when the server starts.
+   Each record is framed by its length and a checksum, so a record that was only partly written before a crash is detected.
+   Records are synced to disk through its LogSync, before the change or the response they belong to can be seen */
+pub(crate) struct ResponseLog {
+    path: PathBuf,
+    file: Arc<File>,
+    records: usize, // the number of records written since the log was last compacted
+    sync: Arc<LogSync>
+}
+
+impl ResponseLog {
+    // the log is compacted once it has this many more records than are needed to describe the current state
+    const COMPACT_THRESHOLD: usize = 4096;
+
+    // FNV-1a, which is enough to catch torn writes
+    fn checksum(buf: &[u8]) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        for byte in buf {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        return hash;
+    }
+
+    // frames a record by its length and checksum
+    fn frame(record: &LogRecord) -> Vec<u8> {
+        let body = record.to_bytes();
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend((body.len() as u32).to_be_bytes());
+        buf.extend(ResponseLog::checksum(&body).to_be_bytes());
+        buf.extend(body);
+        return buf;
+    }
+
+    // reads a single framed record, returning None if it is incomplete or corrupted
+    fn read_frame(reader: &mut Reader) -> Option<LogRecord> {
+        let len = reader.read_int().ok()?;
+        let checksum = reader.read_int().ok()?;
+        let body = reader.take(len as usize).ok()?;
+        if ResponseLog::checksum(body) != checksum {
+            return None;
+        }
+        return LogRecord::from_bytes(body);
+    }
+
+    // opens (or creates) the log and reads back every intact record, stopping at the first one that isn't
+    pub(crate) fn open(path: &Path) -> Result<(ResponseLog, Vec<LogRecord>), Error> {
+        let mut file = File::options().read(true).append(true).create(true).open(path)?;
+        let mut buf: Vec<u8> = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let mut records: Vec<LogRecord> = Vec::new();
+        let mut reader = Reader::new(&buf);
+        let mut end = 0;
+        while let Some(record) = ResponseLog::read_frame(&mut reader) {
+            records.push(record);
+            end = buf.len() - reader.remaining();
+        }
+        let ignored = buf.len() - end;
+        if ignored > 0 {
+            // most likely the server crashed while writing the last record, which was never acted upon
+            println!("{style_bold}{color_blue}[ResponseLog]:{style_reset} Ignoring {ignored} bytes of incomplete records at the end of the log");
+        }
+        let count = records.len();
+        let file = Arc::new(file);
+        let sync = Arc::new(LogSync{file: Mutex::new(file.clone()), written: AtomicU64::new(0), synced: Mutex::new(0)});
+        return Ok((ResponseLog{path: path.to_path_buf(), file, records: count, sync}, records));
+    }
+
+    // appends a record, which only reaches the disk once it has been synced
+    fn append(&mut self, record: &LogRecord) {
+        let result = self.file.as_ref().write_all(&ResponseLog::frame(record));
+        if result.is_err() {
+            let err = result.unwrap_err();
+            println!("{style_bold}{color_blue}[ResponseLog]:{style_reset} Error writing to the log: {err}");
+        }
+        self.records += 1;
+        self.sync.written.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /* replaces the log with just the given records, which describe the current state
+       the new log is written to a temporary file first, so a crash midway leaves the old log intact */
+    fn compact(&mut self, records: &[LogRecord]) {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let result = File::create(&tmp_path).and_then(|mut tmp| {
+            let mut buf: Vec<u8> = Vec::new();
+            for record in records {
+                buf.extend(ResponseLog::frame(record));
+            }
+            tmp.write_all(&buf)?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, &self.path)?;
+            return File::options().append(true).open(&self.path);
+        });
+        match result {
+            Ok(file) => {
+                let count = records.len();
+                println!("{style_bold}{color_blue}[ResponseLog]:{style_reset} Compacted the log down to {count} records");
+                self.file = Arc::new(file);
+                self.records = count;
+                // the compacted log was synced as a whole, which covers every record written before it
+                let mut synced = self.sync.synced.lock().unwrap();
+                *self.sync.file.lock().unwrap() = self.file.clone();
+                *synced = self.sync.written.load(Ordering::SeqCst);
+            },
+            Err(err) => println!("{style_bold}{color_blue}[ResponseLog]:{style_reset} Error compacting the log: {err}"),
+        }
+    }
+}
+
+/* ResponseManager keeps a track of the responses generated for each client
+   as well as the session id associated with them for at-most-once semantics.
+   The responses are bounded by the retention policy, so that long running clients don't grow it forever */
+pub struct ResponseManager {
+    response_map: HashMap<Peer, ResponseHistory>, // map client to the responses generated for each of its request numbers
+    pub(crate) session_map: HashMap<Peer, u32>, // map client to session id
+    last_active: HashMap<Peer, u128>, // map client to the last time it sent a request
+    pub(crate) in_flight: HashMap<(Peer, u32), bool>, // client and request number of the requests currently being serviced, and whether their intent was logged
+    policy: RetentionPolicy,
+    pub(crate) log: Option<ResponseLog> // every change is written here first if the server was given a log file
+}
+
+impl ResponseManager {
+    pub fn new(policy: RetentionPolicy) -> ResponseManager {
+        return ResponseManager{response_map: HashMap::new(), session_map: HashMap::new(), last_active: HashMap::new(), in_flight: HashMap::new(), policy, log: None};
+    }
+
+    /* how far the log has to be synced to cover every record written so far, None if there is no log
+       the sync itself is left to the caller, which does it once it has let go of the ResponseManager */
+    pub(crate) fn log_position(&self) -> Option<(Arc<LogSync>, u64)> {
+        let sync = &self.log.as_ref()?.sync;
+        return Some((sync.clone(), sync.written.load(Ordering::SeqCst)));
+    }
+
+    /* marks a request as being serviced, logging its intent first if it changes a file
+       if the server crashes before its response is logged, the request is known to have maybe been carried out */
+    pub(crate) fn begin_request(&mut self, addr: Peer, req_no: u32, modifies: bool) {
+        self.in_flight.insert((addr, req_no), modifies);
+        if modifies {
+            self.write_log(LogRecord::Intent{addr, req_no});
+        }
+    }
+
+    // writes a change to the log if there is one, compacting it when it has grown too large
+    fn write_log(&mut self, record: LogRecord) {
+        if self.log.is_none() {
+            return;
+        }
+        let live = self.session_map.len() + self.response_map.values().map(|history| history.responses.len()).sum::<usize>();
+        let log = self.log.as_mut().unwrap();
+        log.append(&record);
+        if log.records > live + ResponseLog::COMPACT_THRESHOLD {
+            self.compact_log();
+        }
+    }
+
+    // rewrites the log with only the records needed to restore the current sessions, floors and responses
+    pub(crate) fn compact_log(&mut self) {
+        let mut records: Vec<LogRecord> = Vec::new();
+        for (addr, session_id) in self.session_map.iter() {
+            records.push(LogRecord::Session{addr: *addr, session_id: *session_id});
+        }
+        for (addr, history) in self.response_map.iter() {
+            // the floor goes first, since a response kept below it would be dropped again otherwise
+            if history.floor > 0 {
+                records.push(LogRecord::Acknowledge{addr: *addr, ack: history.floor});
+            }
+            for (req_no, response) in history.responses.iter() {
+                records.push(LogRecord::Response{addr: *addr, req_no: *req_no, response: response.clone()});
+            }
+        }
+        // requests that are still being carried out need their intent to outlive the compaction
+        for ((addr, req_no), logged) in self.in_flight.iter() {
+            if *logged {
+                records.push(LogRecord::Intent{addr: *addr, req_no: *req_no});
+            }
+        }
+        if let Some(log) = self.log.as_mut() {
+            log.compact(&records);
+        }
+    }
+
+    /* applies the records read from the log at startup
+       this must happen before the log is attached, so that the records aren't written again */
+    pub(crate) fn replay(&mut self, records: Vec<LogRecord>) {
+        let count = records.len();
+        println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Replaying {count} records from the log");
+        let mut intents: HashSet<(Peer, u32)> = HashSet::new();
+        for record in records {
+            match record {
+                LogRecord::Session { addr, session_id } => self.start_session(addr, session_id),
+                LogRecord::Flush { addr } => {
+                    self.flush_client(&addr);
+                    intents.retain(|(client, _)| *client != addr);
+                },
+                LogRecord::Response { addr, req_no, response } => {
+                    intents.remove(&(addr, req_no));
+                    self.add_entry(&addr, req_no, response);
+                },
+                LogRecord::Acknowledge { addr, ack } => {
+                    self.acknowledge(&addr, ack);
+                    intents.retain(|(client, req_no)| *client != addr || *req_no >= ack);
+                },
+                LogRecord::Intent { addr, req_no } => {
+                    intents.insert((addr, req_no));
+                },
+            }
+        }
+        /* the server went down while these requests were being carried out, so whether their change was made is unknown
+           they are answered as stale from now on, which stops a retransmission from making the change a second time */
+        for (addr, req_no) in intents {
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Req No. {req_no} from {addr} was interrupted by the restart");
+            let response = ResponseMarshal{status: StatusCode::STALE, timestamp: None, data: b"Request was interrupted by a server restart and may have been carried out".to_vec()};
+            self.add_entry(&addr, req_no, response);
+        }
+        // idle time of the restored clients is counted from now, since they couldn't reach the server while it was down
+        let clients: Vec<Peer> = self.session_map.keys().chain(self.response_map.keys()).copied().collect();
+        for addr in clients {
+            self.touch_client(&addr);
+        }
+    }
+
+    // record the session id of a client
+    pub fn start_session(&mut self, addr: Peer, session_id: u32) {
+        self.session_map.insert(addr, session_id);
+        self.write_log(LogRecord::Session{addr, session_id});
+    }
+
+    // clear out any entries belonging to a certain client
+    pub fn flush_client(&mut self, addr: &Peer) {
+        let mut flushed = false;
+        if self.response_map.contains_key(addr) {
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Clearing response entires of this client");
+            self.response_map.remove(addr);
+            flushed = true;
+        }
+
+        if self.session_map.contains_key(addr) {
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Clearing session entry of this client");
+            self.session_map.remove(addr);
+            flushed = true;
+        }
+
+        // requests still being serviced belong to the old session, so their responses shouldn't be saved
+        self.in_flight.retain(|(client, _), _| client != addr);
+
+        if flushed {
+            self.write_log(LogRecord::Flush{addr: *addr});
+        }
+    }
+
+    // clear out everything about a client, including when it was last seen
+    pub fn forget_client(&mut self, addr: &Peer) {
+        self.flush_client(addr);
+        self.last_active.remove(addr);
+    }
+
+    // record that a client has just sent a request
+    pub fn touch_client(&mut self, addr: &Peer) {
+        self.last_active.insert(*addr, get_time());
+    }
+
+    // forget clients that have been idle for longer than the policy allows
+    pub fn expire_idle(&mut self) {
+        let time = get_time();
+        let idle_timeout = self.policy.idle_timeout;
+        let idle: Vec<Peer> = self.last_active.iter().filter(|(_, last)| time > *last + idle_timeout).map(|(addr, _)| *addr).collect();
+        for addr in idle {
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} {addr} has been idle for more than {idle_timeout}ms, forgetting its session");
+            self.forget_client(&addr);
+        }
+    }
+
+    /* the client has received the responses to every request numbered below ack, so they don't need to be kept anymore
+       the floor is raised to ack even if nothing was saved, while the log only needs a record when a response is dropped */
+    pub fn acknowledge(&mut self, addr: &Peer, ack: u32) {
+        let history = self.response_map.entry(*addr).or_default();
+        if ack <= history.floor {
+            return;
+        }
+        let mut count = 0;
+        while history.responses.first_key_value().is_some_and(|(req_no, _)| *req_no < ack) {
+            history.pop_oldest();
+            count += 1;
+        }
+        history.floor = ack;
+        if count > 0 {
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Client acknowledged up to req no. {ack}, dropped {count} saved responses");
+            self.write_log(LogRecord::Acknowledge{addr: *addr, ack});
+        }
+    }
+
+    // whether a request is below the floor of its client, which means it must not be carried out even though its response is gone
+    pub fn is_stale(&self, addr: &Peer, req_no: u32) -> bool {
+        return self.response_map.get(addr).is_some_and(|history| req_no < history.floor);
+    }
+
+    // check if a response entry for a request number exists for a specific client
+    pub fn has_entry(&self, addr: &Peer, req_no: u32) -> bool {
+        if self.response_map.contains_key(addr) {
+            let history = self.response_map.get(addr).unwrap();
+            return history.responses.contains_key(&req_no);
+        }
+        return false
+    }
+
+    // return the entry (it is assumed that has_entry is checked before calling this)
+    pub fn get_entry(&self, addr: &Peer, req_no: u32) -> &ResponseMarshal {
+        let history = self.response_map.get(addr).unwrap();
+        return history.responses.get(&req_no).unwrap();
+    }
+
+    /* Add a response to the map, dropping the oldest responses of the client if it has too many
+       the response just added is always kept, even if it is too large or too old by itself, since its request has already been carried out */
+    pub fn add_entry(&mut self, addr: &Peer, req_no: u32, response: ResponseMarshal) {
+        println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Saving response of req no. {req_no} from this client");
+        let record = LogRecord::Response{addr: *addr, req_no, response: response.clone()};
+        // create an empty history first if this client doesn't have one
+        let history = self.response_map.entry(*addr).or_default();
+        history.bytes += response.size();
+        if let Some(replaced) = history.responses.insert(req_no, response) {
+            history.bytes -= replaced.size();
+        }
+        // the responses dropped below don't need records of their own, replaying this one drops them again
+        self.write_log(record);
+        let history = self.response_map.get_mut(addr).unwrap();
+
+        // only the window of request numbers ending at the latest one is kept
+        let latest = *history.responses.last_key_value().unwrap().0;
+        let window_start = latest.saturating_sub(self.policy.window - 1);
+        while history.responses.first_key_value().is_some_and(|(dropped, _)| *dropped < window_start) {
+            let dropped = match history.pop_oldest_except(req_no) {
+                Some(dropped) => dropped,
+                None => break,
+            };
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Dropping response of req no. {dropped} as it is outside the window");
+        }
+
+        // and the oldest are dropped until the client is within its byte limit
+        while history.bytes > self.policy.max_bytes {
+            let dropped = match history.pop_oldest_except(req_no) {
+                Some(dropped) => dropped,
+                None => break,
+            };
+            let bytes = history.bytes;
+            println!("{style_bold}{color_blue}[ResponseManager]:{style_reset} Dropping response of req no. {dropped}, {bytes} bytes are still saved for this client");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use crate::testing::TempDir;
+
+    fn client() -> Peer {
+        return Peer::Udp("127.0.0.1:45601".parse().unwrap());
+    }
+
+    fn response(data: &[u8]) -> ResponseMarshal {
+        return ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: data.to_vec()};
+    }
+
+    fn manager(window: u32, max_bytes: usize, idle_timeout: u128) -> ResponseManager {
+        return ResponseManager::new(RetentionPolicy{window, max_bytes, idle_timeout});
+    }
+
+    // the request numbers whose responses are saved for the client, out of the first few
+    fn saved(manager: &ResponseManager) -> Vec<u32> {
+        return (0..10).filter(|req_no| manager.has_entry(&client(), *req_no)).collect();
+    }
+
+    #[test]
+    fn only_the_latest_window_of_responses_is_kept() {
+        let mut manager = manager(3, usize::MAX, 60_000);
+        for req_no in 1..=5 {
+            manager.add_entry(&client(), req_no, response(b"ok"));
+        }
+        assert_eq!(saved(&manager), vec![3, 4, 5]);
+        // the dropped requests can't be carried out again
+        assert!(manager.is_stale(&client(), 2));
+        assert!(!manager.is_stale(&client(), 3));
+        assert!(!manager.is_stale(&client(), 6));
+    }
+
+    #[test]
+    fn acknowledged_responses_are_dropped() {
+        let mut manager = manager(10, usize::MAX, 60_000);
+        for req_no in 1..=3 {
+            manager.add_entry(&client(), req_no, response(b"ok"));
+        }
+        manager.acknowledge(&client(), 3);
+        assert_eq!(saved(&manager), vec![3]);
+        assert!(manager.is_stale(&client(), 2));
+        // an older acknowledgement arriving late doesn't lower the floor again
+        manager.acknowledge(&client(), 1);
+        assert!(manager.is_stale(&client(), 2));
+        manager.acknowledge(&client(), 8);
+        assert!(saved(&manager).is_empty());
+        assert!(manager.is_stale(&client(), 7));
+    }
+
+    #[test]
+    fn oldest_responses_are_dropped_past_the_byte_limit() {
+        let size = response(b"0123456789").size();
+        let mut manager = manager(10, 2 * size, 60_000);
+        manager.add_entry(&client(), 1, response(b"0123456789"));
+        manager.add_entry(&client(), 2, response(b"0123456789"));
+        assert_eq!(saved(&manager), vec![1, 2]);
+        manager.add_entry(&client(), 3, response(b"0123456789"));
+        assert_eq!(saved(&manager), vec![2, 3]);
+        // the latest response is kept even if it is too large by itself
+        manager.add_entry(&client(), 4, response(&[0; 100]));
+        assert_eq!(saved(&manager), vec![4]);
+        assert!(manager.is_stale(&client(), 3));
+    }
+
+    #[test]
+    fn idle_clients_are_forgotten() {
+        let mut manager = manager(10, usize::MAX, 0);
+        manager.start_session(client(), 7);
+        manager.add_entry(&client(), 1, response(b"ok"));
+        manager.touch_client(&client());
+        thread::sleep(Duration::from_millis(5));
+        manager.expire_idle();
+        assert!(manager.session_map.is_empty());
+        assert!(saved(&manager).is_empty());
+    }
+
+    // a log file in a fresh directory, which is removed along with it
+    fn temp_log(name: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(&format!("log-{name}"));
+        let path = dir.0.join("responses.log");
+        return (dir, path);
+    }
+
+    fn records() -> Vec<LogRecord> {
+        let addr: Peer = Peer::Udp("127.0.0.1:45601".parse().unwrap());
+        return vec![
+            LogRecord::Session{addr, session_id: 7},
+            LogRecord::Intent{addr, req_no: 1},
+            LogRecord::Response{addr, req_no: 1, response: ResponseMarshal{status: StatusCode::GOOD, timestamp: Some(42), data: b"Inserted".to_vec()}},
+            LogRecord::Response{addr, req_no: 2, response: ResponseMarshal{status: StatusCode::NOT_FOUND, timestamp: None, data: Vec::new()}},
+            LogRecord::Acknowledge{addr, ack: 2},
+            LogRecord::Flush{addr},
+        ];
+    }
+
+    fn write_records(path: &Path, records: &[LogRecord]) {
+        let (mut log, replayed) = ResponseLog::open(path).unwrap();
+        assert!(replayed.is_empty());
+        for record in records {
+            log.append(record);
+        }
+        let written = log.sync.written.load(Ordering::SeqCst);
+        log.sync.wait(written);
+    }
+
+    // records have no equality of their own, so they are compared by their bytes
+    fn as_bytes(records: &[LogRecord]) -> Vec<Vec<u8>> {
+        return records.iter().map(|record| record.to_bytes()).collect();
+    }
+
+    #[test]
+    fn checksum_is_fnv1a() {
+        assert_eq!(ResponseLog::checksum(b""), 0x811c9dc5);
+        assert_eq!(ResponseLog::checksum(b"a"), 0xe40c292c);
+        assert_eq!(ResponseLog::checksum(b"foobar"), 0xbf9cf968);
+    }
+
+    #[test]
+    fn log_replays_every_record_written() {
+        let (_dir, log) = temp_log("round-trip");
+        write_records(&log, &records());
+        let (_, replayed) = ResponseLog::open(&log).unwrap();
+        assert_eq!(as_bytes(&replayed), as_bytes(&records()));
+    }
+
+    #[test]
+    fn log_stops_at_a_torn_record() {
+        let (_dir, log) = temp_log("torn");
+        write_records(&log, &records());
+        let len = fs::metadata(&log).unwrap().len();
+        File::options().write(true).open(&log).unwrap().set_len(len - 3).unwrap();
+        let (_, replayed) = ResponseLog::open(&log).unwrap();
+        assert_eq!(as_bytes(&replayed), as_bytes(&records()[..records().len() - 1]));
+    }
+
+    #[test]
+    fn log_stops_at_a_corrupted_record() {
+        let (_dir, log) = temp_log("corrupted");
+        write_records(&log, &records());
+        let mut buf = fs::read(&log).unwrap();
+        // the acknowledgement is the second to last record, and its last byte is part of the number acknowledged
+        let flush_len = ResponseLog::frame(&records()[5]).len();
+        let index = buf.len() - flush_len - 1;
+        buf[index] ^= 0xff;
+        fs::write(&log, &buf).unwrap();
+        let (_, replayed) = ResponseLog::open(&log).unwrap();
+        assert_eq!(as_bytes(&replayed), as_bytes(&records()[..4]));
+    }
+
+    #[test]
+    fn compacted_log_holds_only_the_given_records() {
+        let (_dir, log) = temp_log("compact");
+        write_records(&log, &records());
+        let (mut response_log, replayed) = ResponseLog::open(&log).unwrap();
+        response_log.compact(&replayed[..2]);
+        response_log.append(&records()[4]);
+        drop(response_log);
+        let (_, replayed) = ResponseLog::open(&log).unwrap();
+        assert_eq!(as_bytes(&replayed), vec![records()[0].to_bytes(), records()[1].to_bytes(), records()[4].to_bytes()]);
+    }
+}
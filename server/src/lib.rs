@@ -0,0 +1,28 @@
+/* The remote file access server as a library
+   a Server is configured through its builder and then services requests from its clients, until the process exits or it is shut down.
+   The protocol types, the storage backends and the managers are exposed as well, so they can be used on their own */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod fault;
+pub mod handler;
+pub mod history;
+pub mod monitor;
+pub mod poll;
+pub mod protocol;
+pub mod server;
+pub mod storage;
+pub mod transport;
+
+#[cfg(test)]
+mod testing;
+
+pub use server::{Server, ServerBuilder, ServerHandle};
+
+// used to get the current time as epoch milliseconds
+pub(crate) fn get_time() -> u128 {
+    let start = SystemTime::now();
+    let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap();
+    return since_the_epoch.as_millis();
+}
+
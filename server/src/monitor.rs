@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use inline_colorization::*;
+
+use crate::fault::DatagramKey;
+use crate::get_time;
+use crate::protocol::{Delta, EntryChange, EntryEvent, Notification, ResponseMarshal, StatusCode};
+use crate::storage::Storage;
+use crate::transport::{Peer, Transport};
+
+// How updates pushed over UDP are retransmitted until the client acknowledges them
+#[derive(Clone, Copy)]
+pub struct NotifyPolicy {
+    pub timeout: u128, // milliseconds to wait for an acknowledgement before sending an update again
+    pub retries: u32 // the number of times an update is sent again before giving up on it
+}
+
+/* The part of a file a monitor is on, which follows its content as bytes are inserted or deleted before it
+   a change that replaces bytes at its edges takes the new bytes into the range, while an insertion right at its start or end is left out */
+#[derive(Clone, Copy)]
+pub struct MonitorRange {
+    pub offset: u64,
+    pub length: u64
+}
+
+impl MonitorRange {
+    /* moves the range along with a change, returning the change as seen from within the range
+       None means the change neither touches nor shifts the range, so there is nothing to tell the client */
+    fn adjust(&mut self, change: &Delta) -> Option<Delta> {
+        let start = self.offset;
+        let end = self.offset + self.length;
+        let change_end = change.offset + change.removed;
+        // bytes replaced inside the range, or an insertion strictly within it
+        let overlaps = if change.removed == 0 { start < change.offset && change.offset < end } else { change.offset < end && change_end > start };
+        if !overlaps {
+            // a change after the range leaves it alone
+            if change_end > start {
+                return None;
+            }
+            // one before it moves it along, without changing its content
+            let offset = start + change.data.len() as u64 - change.removed;
+            if offset == start {
+                return None;
+            }
+            self.offset = offset;
+            return Some(Delta{op: change.op, offset: 0, removed: 0, data: Vec::new(), length: self.length});
+        }
+        let offset = change.offset.max(start) - start;
+        let removed = change_end.min(end) - change.offset.max(start);
+        let length = self.length - removed + change.data.len() as u64;
+        *self = MonitorRange{offset: change.offset.min(start), length};
+        return Some(Delta{op: change.op, offset, removed, data: change.data.clone(), length});
+    }
+
+    // keeps the range within a file that has been replaced as a whole
+    fn clamp(&mut self, len: u64) {
+        self.offset = self.offset.min(len);
+        self.length = self.length.min(len - self.offset);
+    }
+
+    // the bytes of the range, cut short where the content ends
+    pub fn slice<'b>(&self, content: &'b [u8]) -> &'b [u8] {
+        let start = (self.offset as usize).min(content.len());
+        let end = ((self.offset + self.length) as usize).min(content.len());
+        return &content[start..end];
+    }
+}
+
+// What a client asked for when it started monitoring
+#[derive(Clone, Copy, Default)]
+pub struct MonitorOptions {
+    pub delta: bool, // whether the client is sent what changed rather than the whole file
+    pub range: Option<MonitorRange>, // the part of the file being monitored, None for all of it
+    pub recursive: bool // for a directory, whether files in the directories under it are included
+}
+
+/* A struct to represent a monitor interval
+   the monitored path is either a file, or a directory whose files are reported on as they change */
+#[derive(Clone)]
+struct MonitorInterval {
+    id: u32, // tells the client which monitor an update belongs to
+    name: String, // the path as the client gave it, which is what the client is shown when listing its monitors
+    end_time: u128,
+    seq: u32, // the sequence number of the latest update sent for this monitor, starting from 0 before the first one
+    options: MonitorOptions
+}
+
+// An update that has been sent over UDP but not acknowledged yet
+struct PendingNotification {
+    response: ResponseMarshal,
+    sent_at: u128,
+    attempts: u32 // the number of times it has been sent again
+}
+
+/* Updates for clients that were put together while the MonitorManager was locked, to be sent once it has been let go of
+   so that every other request doesn't wait on the lock while clients are being written to */
+#[must_use = "the updates are only sent once the outbox is"]
+pub struct Outbox {
+    updates: Vec<(Peer, ResponseMarshal, DatagramKey)>
+}
+
+impl Outbox {
+    // sends every update over whichever transport its client is using
+    pub fn send(self, transport: &Transport) {
+        for (addr, response, key) in self.updates {
+            transport.send(&response, addr, key);
+        }
+    }
+}
+
+/* The monitor manager maintains the intervals of every client monitoring each file
+   a client has at most one interval on a file, so a file and a client are enough to tell which monitor is meant.
+   Updates are numbered per monitor and kept until the client acknowledges them, so a lost datagram is sent again.
+   Nothing is sent while it is locked, the updates are handed back in an Outbox instead */
+pub struct MonitorManager {
+    dict: HashMap<PathBuf, HashMap<Peer, MonitorInterval>>,
+    pending: HashMap<(Peer, u32, u32), PendingNotification>, // unacknowledged updates by client, monitor id and sequence number
+    next_id: u32,
+    policy: NotifyPolicy,
+    outbox: Vec<(Peer, ResponseMarshal, DatagramKey)> // the updates put together so far, which are handed back once the manager is done
+}
+
+impl MonitorManager {
+    pub fn new(policy: NotifyPolicy) -> MonitorManager {
+        return MonitorManager{dict: HashMap::new(), pending: HashMap::new(), next_id: 0, policy, outbox: Vec::new()};
+    }
+
+    // hands back the updates put together so far
+    fn take_outbox(&mut self) -> Outbox {
+        return Outbox{updates: std::mem::take(&mut self.outbox)};
+    }
+
+    /* adds a monitor interval to the map
+       monitoring a file again replaces the interval the client already had on it, which extends (or shortens) it
+       while keeping its id and sequence numbers */
+    pub fn add_interval(&mut self, file: PathBuf, name: String, addr: Peer, interval: u32, options: MonitorOptions) {
+        let current_time = get_time();
+        // the monitor time starts now will expire when we pass the interval
+        let end_time = current_time + interval as u128;
+        let file_str = file.to_string_lossy();
+        println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Adding monitor on {file_str} for {addr}, ending at {end_time} ({current_time} + {interval}");
+        // add to the existing map, creating an empty one first if this file has no monitors
+        let monitors = self.dict.entry(file).or_default();
+        if let Some(monitor) = monitors.get_mut(&addr) {
+            monitor.name = name;
+            monitor.end_time = end_time;
+            monitor.options = options;
+            return;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        monitors.insert(addr, MonitorInterval{id, name, end_time, seq: 0, options});
+    }
+
+    // cancels the monitor a client has on a file, returning false if it had none that was still running
+    pub fn remove_interval(&mut self, file: &Path, addr: Peer) -> bool {
+        let time : u128 = get_time();
+        let monitor = match self.dict.get_mut(file).and_then(|monitors| monitors.remove(&addr)) {
+            Some(monitor) => monitor,
+            None => return false,
+        };
+        if self.dict.get(file).is_some_and(|monitors| monitors.is_empty()) {
+            self.dict.remove(file);
+        }
+        // updates the client no longer wants aren't sent again
+        self.pending.retain(|(peer, id, _), _| *peer != addr || *id != monitor.id);
+        if time > monitor.end_time {
+            return false;
+        }
+        let file_str = file.to_string_lossy();
+        println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Cancelled monitor on {file_str} for {addr}");
+        return true;
+    }
+
+    // the monitors a client has that are still running, as the path it gave along with the milliseconds left, sorted by path
+    pub fn list_intervals(&mut self, addr: Peer) -> Vec<(String, u128)> {
+        // the same time is used for both, so every monitor that is kept has time left
+        let time : u128 = get_time();
+        self.clear_expired(time);
+        let mut list: Vec<(String, u128)> = Vec::new();
+        for monitors in self.dict.values() {
+            if let Some(monitor) = monitors.get(&addr) {
+                list.push((monitor.name.clone(), monitor.end_time - time));
+            }
+        }
+        list.sort();
+        return list;
+    }
+
+    // drops every monitor of a client, along with any updates it hasn't acknowledged, which is done when it disconnects
+    pub fn forget_client(&mut self, addr: Peer) {
+        for monitors in self.dict.values_mut() {
+            monitors.remove(&addr);
+        }
+        self.dict.retain(|_, monitors| !monitors.is_empty());
+        self.pending.retain(|(peer, _, _), _| *peer != addr);
+        println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Dropped every monitor of {addr}");
+    }
+
+    // drops the monitors that have expired by the given time, which otherwise only happens once their file changes
+    fn clear_expired(&mut self, time: u128) {
+        for monitors in self.dict.values_mut() {
+            monitors.retain(|_, monitor| time <= monitor.end_time);
+        }
+        self.dict.retain(|_, monitors| !monitors.is_empty());
+    }
+
+    // the file a client's monitor is on along with the latest sequence number sent for it and its range, used to resync the client
+    pub fn find_monitor(&self, addr: Peer, id: u32) -> Option<(PathBuf, u32, Option<MonitorRange>)> {
+        for (file, monitors) in self.dict.iter() {
+            if let Some(monitor) = monitors.get(&addr).filter(|monitor| monitor.id == id) {
+                return Some((file.clone(), monitor.seq, monitor.options.range));
+            }
+        }
+        return None;
+    }
+
+    // the paths that are being monitored, along with whether any monitor on a directory also covers the directories under it
+    pub fn watched_paths(&mut self) -> Vec<(PathBuf, bool)> {
+        self.clear_expired(get_time());
+        return self.dict.iter().map(|(file, monitors)| (file.clone(), monitors.values().any(|monitor| monitor.options.recursive))).collect();
+    }
+
+    // numbers an update and puts it in the outbox for a client, keeping it until it is acknowledged if it goes out over UDP
+    fn notify(&mut self, addr: Peer, monitor: &mut MonitorInterval, update: ResponseMarshal) {
+        monitor.seq = monitor.seq.wrapping_add(1);
+        let response = Notification{monitor_id: monitor.id, seq: monitor.seq, update}.to_response();
+        // streams don't lose anything, so there is nothing to send again
+        if let Peer::Udp(_) = addr {
+            self.pending.insert((addr, monitor.id, monitor.seq), PendingNotification{response: response.clone(), sent_at: get_time(), attempts: 0});
+        }
+        self.outbox.push((addr, response, DatagramKey::notification(monitor.id, monitor.seq)));
+    }
+
+    // the client has received an update, so it doesn't have to be sent again
+    pub fn acknowledge(&mut self, addr: Peer, id: u32, seq: u32) {
+        if self.pending.remove(&(addr, id, seq)).is_some() {
+            println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} {addr} acknowledged update {seq} of monitor {id}");
+        }
+    }
+
+    // sends every update that hasn't been acknowledged in time again, giving up on those that have run out of retries
+    pub fn retransmit(&mut self) -> Outbox {
+        let time : u128 = get_time();
+        let policy = self.policy;
+        let outbox = &mut self.outbox;
+        self.pending.retain(|(addr, id, seq), pending| {
+            if time < pending.sent_at + policy.timeout {
+                return true;
+            }
+            if pending.attempts >= policy.retries {
+                // the client finds out about the missing update from the sequence number of the next one
+                println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Giving up on update {seq} of monitor {id} for {addr}");
+                return false;
+            }
+            pending.attempts += 1;
+            pending.sent_at = time;
+            let attempts = pending.attempts;
+            println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Sending update {seq} of monitor {id} to {addr} again ({attempts} of {})", policy.retries);
+            outbox.push((*addr, pending.response.clone(), DatagramKey::notification(*id, *seq)));
+            return true;
+        });
+        return self.take_outbox();
+    }
+
+    /* informs every client monitoring a directory that a file in it has been created, changed or removed
+       the directories are found by going up from the file, and those further up than its parent are only of interest to recursive monitors */
+    fn inform_directories(&mut self, file: &Path, event: u8) {
+        let time : u128 = get_time();
+        for (depth, dir) in file.ancestors().skip(1).enumerate() {
+            let mut monitors = match self.dict.remove(dir) {
+                Some(monitors) => monitors,
+                None => continue,
+            };
+            let dir_str = dir.to_string_lossy();
+            // the client is told about the file by its path from the directory
+            let name = file.strip_prefix(dir).unwrap_or(file).to_string_lossy().into_owned();
+            monitors.retain(|addr, monitor| {
+                let end_time = monitor.end_time;
+                if time > end_time {
+                    println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Removing monitor for {addr} on {dir_str} set to expire at {end_time}");
+                    return false;
+                }
+                if depth > 0 && !monitor.options.recursive {
+                    return true;
+                }
+                println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Informing {addr} monitoring {dir_str} of changes on {name}");
+                let change = EntryChange{event, name: name.clone()};
+                let update = ResponseMarshal{status: StatusCode::ENTRY, timestamp: None, data: change.to_bytes()};
+                self.notify(*addr, monitor, update);
+                return true;
+            });
+            if !monitors.is_empty() {
+                self.dict.insert(dir.to_path_buf(), monitors);
+            }
+        }
+    }
+
+    // informs every client monitoring a directory that a file has been created in it, which nobody can be monitoring yet
+    pub fn inform_created(&mut self, file: &Path) -> Outbox {
+        self.inform_directories(file, EntryEvent::CREATED);
+        return self.take_outbox();
+    }
+
+    // informs every client monitoring a file that it has been removed, after which all its monitors are dropped
+    pub fn inform_removed(&mut self, file: &Path) -> Outbox {
+        let time : u128 = get_time();
+        let file_str = file.to_string_lossy();
+        self.inform_directories(file, EntryEvent::REMOVED);
+        if let Some(monitors) = self.dict.remove(file) {
+            for (addr, mut monitor) in monitors {
+                // expired monitors are simply dropped along with the rest
+                if time <= monitor.end_time {
+                    println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Informing {addr} that {file_str} has been removed");
+                    let update = ResponseMarshal{status: StatusCode::NOT_FOUND, timestamp: None, data: b"File has been removed".to_vec()};
+                    self.notify(addr, &mut monitor, update);
+                }
+            }
+        }
+        return self.take_outbox();
+    }
+
+    /* function responsible for checking if clients must be informed of changes as well as clearing expired monitor entries
+       the change itself is given when it can be described as a delta, which is what clients that asked for deltas are sent */
+    pub fn inform_monitors(&mut self, file: PathBuf, storage: &dyn Storage, mtime: u64, change: Option<Delta>) -> Outbox {
+        let time : u128 = get_time();
+        let file_str = file.to_string_lossy();
+        println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Checking if any clients must be informed about changes on {file_str}. Current time is {time}");
+        self.inform_directories(&file, EntryEvent::CHANGED);
+        if self.dict.contains_key(&file) {
+            // the new content is only read when somebody monitoring the file needs all of it
+            let needs_content = change.is_none() || self.dict[&file].values().any(|monitor| !monitor.options.delta);
+            let mut content: Vec<u8> = Vec::new();
+            if needs_content {
+                content = match storage.read_all(&file) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} {err} when reading {file_str}, not informing anyone");
+                        return self.take_outbox();
+                    }
+                };
+            }
+            // the monitors are taken out while they are informed, since numbering an update needs the rest of the manager
+            let mut monitors = self.dict.remove(&file).unwrap();
+            monitors.retain(|addr, monitor| {
+                let end_time = monitor.end_time;
+                if time > end_time {
+                    // monitor has expired and will be removed
+                    println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Removing monitor for {addr} on {file_str} set to expire at {end_time}");
+                    return false;
+                }
+                // a monitor on part of the file only hears about changes to that part, which are described from within it
+                let mut scoped = change.clone();
+                if let Some(range) = monitor.options.range.as_mut() {
+                    match &change {
+                        Some(change) => {
+                            scoped = range.adjust(change);
+                            if scoped.is_none() {
+                                return true;
+                            }
+                        },
+                        None => range.clamp(content.len() as u64),
+                    }
+                }
+                // monitor is valid and thus the client who this monitor belongs to is informed
+                println!("{style_bold}{color_cyan}[MonitorManager]:{style_reset} Informing {addr} of changes on {file_str} set to expire at {end_time}");
+                // the modification time is included so that clients can cache the new content
+                let update = match scoped {
+                    Some(scoped) if monitor.options.delta => ResponseMarshal{status: StatusCode::DELTA, timestamp: Some(mtime), data: scoped.to_bytes()},
+                    _ => {
+                        let data = match &monitor.options.range {
+                            Some(range) => range.slice(&content).to_vec(),
+                            None => content.clone(),
+                        };
+                        ResponseMarshal{status: StatusCode::GOOD, timestamp: Some(mtime), data}
+                    },
+                };
+                self.notify(*addr, monitor, update);
+                return true;
+            });
+            if !monitors.is_empty() {
+                self.dict.insert(file, monitors);
+            }
+        }
+        return self.take_outbox();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use crate::protocol::RequestOperation;
+    use crate::storage::MemoryStorage;
+
+    fn client(port: u16) -> Peer {
+        return Peer::Udp(([127, 0, 0, 1], port).into());
+    }
+
+    fn manager() -> MonitorManager {
+        return MonitorManager::new(NotifyPolicy{timeout: 1000, retries: 3});
+    }
+
+    // the client, monitor id and sequence number of every update in an outbox
+    fn sent(outbox: Outbox) -> Vec<(Peer, u32, u32)> {
+        return outbox.updates.iter().map(|(addr, response, _)| {
+            let notification = Notification::from_bytes(&response.to_bytes()).ok().unwrap();
+            return (*addr, notification.monitor_id, notification.seq);
+        }).collect();
+    }
+
+    // a change to a file monitored for deltas, which is pushed without reading the file
+    fn inform(manager: &mut MonitorManager, file: &str) -> Vec<(Peer, u32, u32)> {
+        let change = Delta{op: RequestOperation::INSERT, offset: 0, removed: 0, data: b"a".to_vec(), length: 1};
+        return sent(manager.inform_monitors(PathBuf::from(file), &MemoryStorage::new(), 1, Some(change)));
+    }
+
+    #[test]
+    fn list_intervals_only_shows_running_monitors() {
+        let mut manager = manager();
+        manager.add_interval(PathBuf::from("/b"), String::from("b"), client(1), 60_000, MonitorOptions::default());
+        manager.add_interval(PathBuf::from("/a"), String::from("a"), client(1), 0, MonitorOptions::default());
+        manager.add_interval(PathBuf::from("/c"), String::from("c"), client(2), 60_000, MonitorOptions::default());
+        thread::sleep(Duration::from_millis(5));
+        let list = manager.list_intervals(client(1));
+        assert_eq!(list.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>(), vec!["b"]);
+        assert!(list[0].1 <= 60_000);
+        // an interval ending right now is either gone or listed with no time left, never with a time that wrapped around
+        manager.add_interval(PathBuf::from("/a"), String::from("a"), client(1), 0, MonitorOptions::default());
+        for _ in 0..100 {
+            assert!(manager.list_intervals(client(1)).iter().all(|(name, left)| name == "b" || *left == 0));
+        }
+    }
+
+    #[test]
+    fn updates_are_numbered_per_monitor() {
+        let mut manager = manager();
+        let stream = Peer::Tcp(([127, 0, 0, 1], 2).into());
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), client(1), 60_000, MonitorOptions{delta: true, ..Default::default()});
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), stream, 60_000, MonitorOptions{delta: true, ..Default::default()});
+        let mut first = inform(&mut manager, "/f");
+        first.sort_by_key(|(_, id, _)| *id);
+        assert_eq!(first, vec![(client(1), 0, 1), (stream, 1, 1)]);
+        let mut second = inform(&mut manager, "/f");
+        second.sort_by_key(|(_, id, _)| *id);
+        assert_eq!(second, vec![(client(1), 0, 2), (stream, 1, 2)]);
+        // monitoring the file again keeps the id and the numbering going
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), client(1), 60_000, MonitorOptions{delta: true, ..Default::default()});
+        assert!(inform(&mut manager, "/f").contains(&(client(1), 0, 3)));
+    }
+
+    #[test]
+    fn updates_are_sent_again_until_acknowledged() {
+        let mut manager = MonitorManager::new(NotifyPolicy{timeout: 0, retries: 2});
+        let stream = Peer::Tcp(([127, 0, 0, 1], 2).into());
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), client(1), 60_000, MonitorOptions{delta: true, ..Default::default()});
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), stream, 60_000, MonitorOptions{delta: true, ..Default::default()});
+        let _ = inform(&mut manager, "/f");
+        let _ = inform(&mut manager, "/f");
+        // only the updates that went out over UDP could have been lost
+        let mut resent = sent(manager.retransmit());
+        resent.sort_by_key(|(_, _, seq)| *seq);
+        assert_eq!(resent, vec![(client(1), 0, 1), (client(1), 0, 2)]);
+        manager.acknowledge(client(1), 0, 1);
+        // acknowledging an update that isn't pending changes nothing
+        manager.acknowledge(client(1), 0, 7);
+        assert_eq!(sent(manager.retransmit()), vec![(client(1), 0, 2)]);
+        // the update has been sent again as many times as allowed, so it is given up on
+        assert!(sent(manager.retransmit()).is_empty());
+        assert!(manager.pending.is_empty());
+    }
+
+    #[test]
+    fn updates_wait_for_the_timeout_before_being_sent_again() {
+        let mut manager = manager();
+        manager.add_interval(PathBuf::from("/f"), String::from("f"), client(1), 60_000, MonitorOptions{delta: true, ..Default::default()});
+        assert_eq!(inform(&mut manager, "/f"), vec![(client(1), 0, 1)]);
+        assert!(sent(manager.retransmit()).is_empty());
+        assert_eq!(manager.pending.len(), 1);
+        // a client that is gone isn't waited on any more
+        manager.forget_client(client(1));
+        assert!(manager.pending.is_empty());
+    }
+
+    fn range() -> MonitorRange {
+        return MonitorRange{offset: 10, length: 5};
+    }
+
+    fn change(op: u8, offset: u64, removed: u64, data: &[u8]) -> Delta {
+        // the length of the file is of no concern to the range
+        return Delta{op, offset, removed, data: data.to_vec(), length: 0};
+    }
+
+    // adjusts the range by a change, returning what the client is told as its offset, removed bytes, data and length
+    fn adjust(range: &mut MonitorRange, change: Delta) -> Option<(u64, u64, Vec<u8>, u64)> {
+        return range.adjust(&change).map(|delta| (delta.offset, delta.removed, delta.data, delta.length));
+    }
+
+    #[test]
+    fn changes_after_the_range_are_ignored() {
+        let mut monitored = range();
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::INSERT, 20, 0, b"ab")), None);
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::DELETE, 15, 3, b"")), None);
+        // an insertion right at the end isn't taken into the range
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::INSERT, 15, 0, b"ab")), None);
+        assert_eq!((monitored.offset, monitored.length), (10, 5));
+    }
+
+    #[test]
+    fn changes_before_the_range_move_it_along() {
+        let mut monitored = range();
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::INSERT, 2, 0, b"abc")), Some((0, 0, Vec::new(), 5)));
+        assert_eq!((monitored.offset, monitored.length), (13, 5));
+        // an insertion right at the start isn't taken into the range either
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::INSERT, 13, 0, b"a")), Some((0, 0, Vec::new(), 5)));
+        assert_eq!((monitored.offset, monitored.length), (14, 5));
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::DELETE, 4, 10, b"")), Some((0, 0, Vec::new(), 5)));
+        assert_eq!((monitored.offset, monitored.length), (4, 5));
+        // an update before the range keeps the length of what it replaces, so the range stays put
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::UPDATE, 0, 2, b"ab")), None);
+        assert_eq!((monitored.offset, monitored.length), (4, 5));
+    }
+
+    #[test]
+    fn changes_within_the_range_are_relative_to_it() {
+        let mut monitored = range();
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::INSERT, 12, 0, b"ab")), Some((2, 0, b"ab".to_vec(), 7)));
+        assert_eq!((monitored.offset, monitored.length), (10, 7));
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::DELETE, 11, 3, b"")), Some((1, 3, Vec::new(), 4)));
+        assert_eq!((monitored.offset, monitored.length), (10, 4));
+    }
+
+    #[test]
+    fn changes_across_an_edge_take_the_new_bytes_in() {
+        let mut monitored = range();
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::UPDATE, 8, 4, b"wxyz")), Some((0, 2, b"wxyz".to_vec(), 7)));
+        assert_eq!((monitored.offset, monitored.length), (8, 7));
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::UPDATE, 13, 4, b"abcd")), Some((5, 2, b"abcd".to_vec(), 9)));
+        assert_eq!((monitored.offset, monitored.length), (8, 9));
+        // deleting everything around the range leaves it empty where it was
+        assert_eq!(adjust(&mut monitored, change(RequestOperation::DELETE, 5, 20, b"")), Some((0, 9, Vec::new(), 0)));
+        assert_eq!((monitored.offset, monitored.length), (5, 0));
+    }
+
+    #[test]
+    fn clamp_keeps_the_range_within_the_file() {
+        let mut monitored = range();
+        monitored.clamp(12);
+        assert_eq!((monitored.offset, monitored.length), (10, 2));
+        monitored.clamp(4);
+        assert_eq!((monitored.offset, monitored.length), (4, 0));
+        assert_eq!(range().slice(b"0123456789abc"), b"abc");
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use inline_colorization::*;
+
+use crate::handler::ServerState;
+use crate::protocol::EntryType;
+
+/* ChangePoller looks for changes made to monitored files outside the server, such as by another program or by hand.
+   Each poll compares what the storage reports for the files being monitored, along with the files in monitored directories,
+   against what it reported before, and informs their monitors the same way a change through the server would */
+pub(crate) struct ChangePoller {
+    scanned: HashSet<PathBuf> // directories that have been listed before, so files found in them from then on are new
+}
+
+impl ChangePoller {
+    pub(crate) fn new() -> ChangePoller {
+        return ChangePoller{scanned: HashSet::new()};
+    }
+
+    // checks every monitored file and directory once
+    pub(crate) fn poll(&mut self, state: &ServerState) {
+        let watched = state.monitor_manager.lock().unwrap().watched_paths();
+        for (path, recursive) in watched.iter() {
+            match state.storage.stat(path) {
+                Ok(info) if info.entry_type == EntryType::DIRECTORY => self.poll_directory(state, path, *recursive),
+                _ => ChangePoller::check_file(state, path, false, true),
+            }
+        }
+        // a directory that is monitored again later is listed afresh, since what happened in it meanwhile wasn't followed
+        self.scanned.retain(|dir| watched.iter().any(|(path, _)| path == dir));
+        // and so are the files in it, which are no longer observed either
+        state.modification_tracker.lock().unwrap().retain_watched(&watched);
+    }
+
+    /* checks the files in a directory, including those in the directories under it if recursive
+       files found on the first listing were already there when the monitor started, so they aren't reported as created */
+    fn poll_directory(&mut self, state: &ServerState, dir: &Path, recursive: bool) {
+        let report_new = !self.scanned.insert(dir.to_path_buf());
+        let mut files: Vec<PathBuf> = Vec::new();
+        let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            let entries = match state.storage.list(&current) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for (name, path, info) in entries {
+                if info.entry_type == EntryType::FILE {
+                    files.push(path);
+                } else if recursive && info.entry_type == EntryType::DIRECTORY && path == current.join(&name) {
+                    // directories reached through a symlink are left out, which could otherwise lead back up the tree
+                    pending.push(path);
+                }
+            }
+        }
+        for file in files.iter() {
+            ChangePoller::check_file(state, file, report_new, false);
+        }
+        // files that were seen before but aren't listed anymore have been removed
+        let observed = state.modification_tracker.lock().unwrap().observed_in(dir, recursive);
+        for file in observed.iter().filter(|file| !files.contains(file)) {
+            ChangePoller::check_file(state, file, report_new, false);
+        }
+    }
+
+    /* compares a file against what the storage reported for it before, informing its monitors of whatever changed
+       the file is locked meanwhile, so the check doesn't race with a request changing it. a monitored file is reported removed even if it was never seen */
+    fn check_file(state: &ServerState, file: &Path, report_new: bool, monitored: bool) {
+        let lock = state.file_locks.get(file);
+        let _guard = lock.write().unwrap();
+        let file_str = file.to_string_lossy();
+        let info = match state.storage.stat(file) {
+            Ok(info) => info,
+            Err(_) => {
+                if state.modification_tracker.lock().unwrap().forget(file) || monitored {
+                    println!("{style_bold}{color_cyan}[ChangePoller]:{style_reset} {file_str} was removed outside the server");
+                    let outbox = state.monitor_manager.lock().unwrap().inform_removed(file);
+                    outbox.send(state.transport);
+                }
+                return;
+            }
+        };
+        if !state.modification_tracker.lock().unwrap().observe(file, &info) {
+            state.catch_up(file);
+            return;
+        }
+        if report_new {
+            println!("{style_bold}{color_cyan}[ChangePoller]:{style_reset} {file_str} was created outside the server");
+            let outbox = state.monitor_manager.lock().unwrap().inform_created(file);
+            outbox.send(state.transport);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::ops::{Deref, DerefMut};
+
+// the wire format itself lives in its own crate, which the Rust client shares
+pub use ::protocol::{CreateFlag, DecodeError, Delta, EntryChange, EntryEvent, EntryType, Fragment, MonitorFlag, Notification, Reader, RequestOperation, ResponseMarshal, StatusCode};
+
+// The handler of a single request, which unmarshals it with its reader as it is serviced
+pub struct RequestHandler<'a> {
+    reader: Reader<'a>
+}
+
+impl<'a> RequestHandler<'a> {
+    pub fn new(buf: &'a [u8]) -> RequestHandler<'a> {
+        return RequestHandler{reader: Reader::new(buf)};
+    }
+}
+
+impl<'a> Deref for RequestHandler<'a> {
+    type Target = Reader<'a>;
+
+    fn deref(&self) -> &Reader<'a> {
+        return &self.reader;
+    }
+}
+
+impl<'a> DerefMut for RequestHandler<'a> {
+    fn deref_mut(&mut self) -> &mut Reader<'a> {
+        return &mut self.reader;
+    }
+}
@@ -0,0 +1,571 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use inline_colorization::*;
+
+use crate::fault::FaultPolicy;
+use crate::get_time;
+use crate::handler::{service_message, ServerState};
+use crate::history::{ResponseLog, ResponseManager, RetentionPolicy};
+use crate::monitor::NotifyPolicy;
+use crate::poll::ChangePoller;
+use crate::storage::{FsStorage, MemoryStorage, Storage};
+use crate::transport::{listen_udp, serve_stream, Peer, Transport};
+
+/* ServerBuilder collects the configuration of a server before anything is bound
+   everything not set here has the same default as the command line of the server binary */
+pub struct ServerBuilder {
+    dir: Option<PathBuf>,
+    storage: Option<Box<dyn Storage>>,
+    at_most_once: bool,
+    udp_address: Option<String>,
+    tcp_address: Option<String>,
+    unix_socket: Option<PathBuf>,
+    fragment_size: usize,
+    policy: RetentionPolicy,
+    history_log: Option<PathBuf>,
+    workers: usize,
+    faults: FaultPolicy,
+    notify_policy: NotifyPolicy,
+    poll_interval: Duration
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        return ServerBuilder{
+            dir: None,
+            storage: None,
+            at_most_once: false,
+            udp_address: Some(String::from("localhost:45600")),
+            tcp_address: None,
+            unix_socket: None,
+            fragment_size: 60000,
+            policy: RetentionPolicy{window: 1024, max_bytes: 16777216, idle_timeout: 3600000},
+            history_log: None,
+            workers: 4,
+            // no faults unless asked for, and a different seed every run unless one is given
+            faults: FaultPolicy{drop_requests: 0.0, drop_replies: 0.0, duplicate_replies: 0.0, delay_replies: 0.0, max_delay: Duration::from_millis(1000), seed: get_time() as u64},
+            notify_policy: NotifyPolicy{timeout: 1000, retries: 5},
+            poll_interval: Duration::from_millis(1000)
+        };
+    }
+}
+
+impl ServerBuilder {
+    // serve the files of a directory, the home directory is used if neither this nor a storage is given
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> ServerBuilder {
+        self.dir = Some(dir.into());
+        return self;
+    }
+
+    // keep files in memory, nothing is persisted once the server stops
+    pub fn in_memory(self) -> ServerBuilder {
+        return self.storage(MemoryStorage::new());
+    }
+
+    // serve files from any storage, which takes precedence over a directory
+    pub fn storage(mut self, storage: impl Storage + 'static) -> ServerBuilder {
+        self.storage = Some(Box::new(storage));
+        return self;
+    }
+
+    // at-most-once semantics if set, at-least-once otherwise
+    pub fn at_most_once(mut self, at_most_once: bool) -> ServerBuilder {
+        self.at_most_once = at_most_once;
+        return self;
+    }
+
+    // the address that datagrams are received on
+    pub fn udp(mut self, address: impl Into<String>) -> ServerBuilder {
+        self.udp_address = Some(address.into());
+        return self;
+    }
+
+    // don't listen for datagrams at all
+    pub fn no_udp(mut self) -> ServerBuilder {
+        self.udp_address = None;
+        return self;
+    }
+
+    // also accept length-prefixed requests over TCP on this address
+    pub fn tcp(mut self, address: impl Into<String>) -> ServerBuilder {
+        self.tcp_address = Some(address.into());
+        return self;
+    }
+
+    // also accept length-prefixed requests on a unix domain socket at this path
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.unix_socket = Some(path.into());
+        return self;
+    }
+
+    // the largest datagram sent, larger responses are split into fragments
+    pub fn fragment_size(mut self, fragment_size: usize) -> ServerBuilder {
+        self.fragment_size = fragment_size;
+        return self;
+    }
+
+    // the number of latest request numbers per client whose responses are kept for at-most-once semantics
+    pub fn history_window(mut self, window: u32) -> ServerBuilder {
+        self.policy.window = window;
+        return self;
+    }
+
+    // the maximum bytes of saved responses per client for at-most-once semantics
+    pub fn history_bytes(mut self, max_bytes: usize) -> ServerBuilder {
+        self.policy.max_bytes = max_bytes;
+        return self;
+    }
+
+    // how long a client can be inactive before its session and saved responses are dropped
+    pub fn session_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.policy.idle_timeout = timeout.as_millis();
+        return self;
+    }
+
+    // the file that saved responses are logged to, so at-most-once semantics survive a restart
+    pub fn history_log(mut self, path: impl Into<PathBuf>) -> ServerBuilder {
+        self.history_log = Some(path.into());
+        return self;
+    }
+
+    // the number of worker threads servicing requests
+    pub fn workers(mut self, workers: usize) -> ServerBuilder {
+        self.workers = workers;
+        return self;
+    }
+
+    // how long to wait for a client to acknowledge a monitor update before sending it again
+    pub fn notify_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.notify_policy.timeout = timeout.as_millis();
+        return self;
+    }
+
+    // the number of times a monitor update is sent again before giving up on it
+    pub fn notify_retries(mut self, retries: u32) -> ServerBuilder {
+        self.notify_policy.retries = retries;
+        return self;
+    }
+
+    // how often monitored files are checked for changes made outside the server, zero turns the checks off
+    pub fn poll_interval(mut self, interval: Duration) -> ServerBuilder {
+        self.poll_interval = interval;
+        return self;
+    }
+
+    // the fraction of received datagrams that are dropped before being serviced, to simulate message loss
+    pub fn drop_requests(mut self, fraction: f64) -> ServerBuilder {
+        self.faults.drop_requests = fraction;
+        return self;
+    }
+
+    // the fraction of datagrams that are dropped instead of being sent
+    pub fn drop_replies(mut self, fraction: f64) -> ServerBuilder {
+        self.faults.drop_replies = fraction;
+        return self;
+    }
+
+    // the fraction of datagrams that are sent twice
+    pub fn duplicate_replies(mut self, fraction: f64) -> ServerBuilder {
+        self.faults.duplicate_replies = fraction;
+        return self;
+    }
+
+    // the fraction of datagrams that are held back for a random time up to max_delay
+    pub fn delay_replies(mut self, fraction: f64, max_delay: Duration) -> ServerBuilder {
+        self.faults.delay_replies = fraction;
+        self.faults.max_delay = max_delay;
+        return self;
+    }
+
+    // the seed of the random faults, the same seed gives the same faults for the same datagrams from and to the same clients
+    pub fn fault_seed(mut self, seed: u64) -> ServerBuilder {
+        self.faults.seed = seed;
+        return self;
+    }
+
+    // checks the configuration, binds every transport and restores the saved responses, without servicing anything yet
+    pub fn build(self) -> Result<Server, Error> {
+        if !(64..=65507).contains(&self.fragment_size) {
+            return Err(Error::new(ErrorKind::InvalidInput, "fragment size must be between 64 and 65507"));
+        }
+        if self.policy.window == 0 || self.workers == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "history window and workers must be at least 1"));
+        }
+        if self.notify_policy.timeout == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "notify timeout must be at least 1ms"));
+        }
+        let fractions = [self.faults.drop_requests, self.faults.drop_replies, self.faults.duplicate_replies, self.faults.delay_replies];
+        if !fractions.iter().all(|fraction| (0.0..=1.0).contains(fraction)) {
+            return Err(Error::new(ErrorKind::InvalidInput, "fault fractions must be between 0 and 1"));
+        }
+        // at least one transport is needed to hear from any client
+        if self.udp_address.is_none() && self.tcp_address.is_none() && self.unix_socket.is_none() {
+            return Err(Error::new(ErrorKind::InvalidInput, "UDP is disabled and no other transport was given"));
+        }
+
+        // files are either kept by the given storage or served from the server file directory
+        let storage: Box<dyn Storage> = match self.storage {
+            Some(storage) => {
+                println!("{style_bold}{color_yellow}[Server]:{style_reset} Server files are kept by the given storage");
+                storage
+            },
+            None => {
+                // use home directory when no server file directory is specified
+                let dir = match self.dir.or_else(dirs::home_dir) {
+                    Some(dir) => dir,
+                    None => return Err(Error::new(ErrorKind::NotFound, "Couldn't find the home directory")),
+                };
+                let storage = FsStorage::new(&dir)?;
+                let dir_str = storage.root().to_string_lossy();
+                println!("{style_bold}{color_yellow}[Server]:{style_reset} Server file directory is {dir_str}");
+                Box::new(storage)
+            }
+        };
+
+        // bind a socket for each transport the server is using
+        let mut udp_socket: Option<UdpSocket> = None;
+        if let Some(address) = &self.udp_address {
+            udp_socket = Some(UdpSocket::bind(address).map_err(|err| Error::new(err.kind(), format!("Couldn't bind to address {address}: {err}")))?);
+            println!("{style_bold}{color_yellow}[Server]:{style_reset} Server bound to udp://{address}");
+        }
+        let mut tcp_listener: Option<TcpListener> = None;
+        if let Some(address) = &self.tcp_address {
+            tcp_listener = Some(TcpListener::bind(address).map_err(|err| Error::new(err.kind(), format!("Couldn't bind to address {address}: {err}")))?);
+            println!("{style_bold}{color_yellow}[Server]:{style_reset} Server bound to tcp://{address}");
+        }
+        #[cfg(unix)]
+        let mut unix_listener: Option<UnixListener> = None;
+        if let Some(socket_path) = &self.unix_socket {
+            let socket_str = socket_path.to_string_lossy();
+            #[cfg(unix)]
+            {
+                // a socket file left behind by an earlier run would make binding fail
+                if std::fs::symlink_metadata(socket_path).is_ok_and(|metadata| std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())) {
+                    let _ = std::fs::remove_file(socket_path);
+                }
+                unix_listener = Some(UnixListener::bind(socket_path).map_err(|err| Error::new(err.kind(), format!("Couldn't bind to unix socket {socket_str}: {err}")))?);
+                println!("{style_bold}{color_yellow}[Server]:{style_reset} Server bound to unix://{socket_str}");
+            }
+            #[cfg(not(unix))]
+            return Err(Error::new(ErrorKind::Unsupported, format!("Unix sockets are not available on this platform, can't bind to {socket_str}")));
+        }
+
+        // restore the saved responses from the log before attaching it, which is then compacted to drop anything incomplete
+        let mut response_manager: ResponseManager = ResponseManager::new(self.policy);
+        if let Some(log_path) = &self.history_log {
+            let (log, records) = ResponseLog::open(Path::new(log_path)).map_err(|err| Error::new(err.kind(), format!("Couldn't open the response log {}: {err}", log_path.to_string_lossy())))?;
+            response_manager.replay(records);
+            response_manager.log = Some(log);
+            response_manager.compact_log();
+        }
+
+        return Ok(Server{
+            storage,
+            at_most_once: self.at_most_once,
+            udp_socket,
+            tcp_listener,
+            #[cfg(unix)]
+            unix_listener,
+            fragment_size: self.fragment_size,
+            response_manager,
+            workers: self.workers,
+            faults: self.faults,
+            notify_policy: self.notify_policy,
+            poll_interval: self.poll_interval
+        });
+    }
+}
+
+/* StopSignal tells the threads of a running server to stop
+   threads that sleep between their rounds wait on it instead, so they are woken up right away */
+pub(crate) struct StopSignal {
+    stopped: Mutex<bool>,
+    changed: Condvar
+}
+
+impl StopSignal {
+    fn new() -> StopSignal {
+        return StopSignal{stopped: Mutex::new(false), changed: Condvar::new()};
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        return *self.stopped.lock().unwrap();
+    }
+
+    // waits for the timeout to pass, returning true if the server was stopped meanwhile
+    fn wait(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        return *self.changed.wait_timeout_while(stopped, timeout, |stopped| !*stopped).unwrap().0;
+    }
+
+    // waits for as long as it takes the server to be stopped
+    fn wait_until_stopped(&self) {
+        let stopped = self.stopped.lock().unwrap();
+        let _stopped = self.changed.wait_while(stopped, |stopped| !*stopped).unwrap();
+    }
+}
+
+// A server that has been bound to its transports and is ready to service requests
+pub struct Server {
+    storage: Box<dyn Storage>,
+    at_most_once: bool,
+    udp_socket: Option<UdpSocket>,
+    tcp_listener: Option<TcpListener>,
+    #[cfg(unix)]
+    unix_listener: Option<UnixListener>,
+    fragment_size: usize,
+    response_manager: ResponseManager,
+    workers: usize,
+    faults: FaultPolicy,
+    notify_policy: NotifyPolicy,
+    poll_interval: Duration
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        return ServerBuilder::default();
+    }
+
+    // the address datagrams are received on, which is useful when binding to port 0
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        return self.udp_socket.as_ref().and_then(|socket| socket.local_addr().ok());
+    }
+
+    // the address TCP connections are accepted on
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        return self.tcp_listener.as_ref().and_then(|listener| listener.local_addr().ok());
+    }
+
+    // services requests from every transport until the process exits
+    pub fn run(self) {
+        self.serve(&StopSignal::new());
+    }
+
+    // services requests on a thread of its own, until it is shut down through the returned handle
+    pub fn start(self) -> ServerHandle {
+        let udp_addr = self.udp_addr();
+        let tcp_addr = self.tcp_addr();
+        #[cfg(unix)]
+        let unix_socket = self.unix_listener.as_ref().and_then(|listener| listener.local_addr().ok()?.as_pathname().map(Path::to_path_buf));
+        let stop = Arc::new(StopSignal::new());
+        let signal = stop.clone();
+        let thread = thread::spawn(move || self.serve(&signal));
+        return ServerHandle{
+            udp_addr,
+            tcp_addr,
+            #[cfg(unix)]
+            unix_socket,
+            stop,
+            thread: Some(thread)
+        };
+    }
+
+    // services requests from every transport until it is told to stop
+    fn serve(self, stop: &StopSignal) {
+        let transport: Transport = Transport::new(self.udp_socket.as_ref(), self.fragment_size, self.faults);
+
+        // Print the configuration for verification
+        if self.at_most_once {
+            println!("{style_bold}{color_yellow}[Server]:{style_reset} Using at-most-once semantics");
+        } else {
+            println!("{style_bold}{color_yellow}[Server]:{style_reset} Using at-least-once semantics");
+        }
+        if transport.faults.is_active() {
+            // the seed is printed so that a run can be reproduced
+            let FaultPolicy{drop_requests, drop_replies, duplicate_replies, delay_replies, max_delay, seed} = self.faults;
+            let max_delay = max_delay.as_millis();
+            println!("{style_bold}{color_red}[Fault]:{style_reset} Injecting faults with seed {seed}: dropping {drop_requests} of requests and {drop_replies} of replies, duplicating {duplicate_replies} and delaying {delay_replies} by up to {max_delay}ms");
+        }
+
+        let state: ServerState = ServerState::new(self.storage, &transport, self.response_manager, self.notify_policy, self.at_most_once);
+
+        // received requests are queued up for the workers, so a slow request doesn't hold up the ones behind it
+        let (queue, requests) = mpsc::channel::<(Vec<u8>, Peer)>();
+        let requests = Mutex::new(requests);
+        let workers = self.workers;
+        println!("{style_bold}{color_yellow}[Server]:{style_reset} Servicing requests with {workers} workers");
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    // the queue is only locked while waiting, so a single idle worker picks up the next request
+                    let next = requests.lock().unwrap().recv();
+                    match next {
+                        Ok((buf, src)) => service_message(&state, &buf, src),
+                        Err(_) => return,
+                    }
+                });
+            }
+
+            // every transport listens on its own thread, and every connection of the stream transports gets one as well
+            if let Some(socket) = &self.udp_socket {
+                let queue = queue.clone();
+                let transport = &transport;
+                scope.spawn(move || listen_udp(socket, transport, queue, stop));
+            }
+            // monitor updates that haven't been acknowledged are checked on regularly to be sent again
+            scope.spawn(|| {
+                while !stop.wait(Duration::from_millis(100)) {
+                    let outbox = state.monitor_manager.lock().unwrap().retransmit();
+                    outbox.send(&transport);
+                }
+            });
+            // files are changed outside the server as well, which is only noticed by checking on them
+            if !self.poll_interval.is_zero() {
+                let interval = self.poll_interval;
+                let state = &state;
+                scope.spawn(move || {
+                    let mut poller = ChangePoller::new();
+                    while !stop.wait(interval) {
+                        poller.poll(state);
+                    }
+                });
+            }
+            // once the server stops, the connections are closed so that their threads finish, and the workers with them
+            scope.spawn(|| {
+                stop.wait_until_stopped();
+                println!("{style_bold}{color_yellow}[Server]:{style_reset} Shutting down");
+                transport.close();
+            });
+            if self.faults.delay_replies > 0.0 {
+                let transport = &transport;
+                scope.spawn(move || transport.deliver_delayed());
+            }
+            if let Some(listener) = &self.tcp_listener {
+                let queue = queue.clone();
+                let transport = &transport;
+                let state = &state;
+                scope.spawn(move || {
+                    for incoming in listener.incoming() {
+                        // the server wakes this up with a connection of its own when it stops
+                        if stop.is_stopped() {
+                            return;
+                        }
+                        let stream = match incoming.and_then(|stream| stream.peer_addr().map(|addr| (stream, addr))) {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                println!("{style_bold}{color_green}[TCP]:{style_reset} Error Accepting Connection: {err}");
+                                continue;
+                            }
+                        };
+                        let queue = queue.clone();
+                        // a client can't come back on a connection that has been closed, so nothing it left behind is of use
+                        scope.spawn(move || {
+                            serve_stream(Box::new(stream.0), Peer::Tcp(stream.1), transport, queue, stop);
+                            state.forget_client(Peer::Tcp(stream.1));
+                        });
+                    }
+                });
+            }
+            #[cfg(unix)]
+            if let Some(listener) = &self.unix_listener {
+                let queue = queue.clone();
+                let transport = &transport;
+                let state = &state;
+                scope.spawn(move || {
+                    let mut connection: u64 = 0;
+                    for incoming in listener.incoming() {
+                        if stop.is_stopped() {
+                            return;
+                        }
+                        let stream = match incoming {
+                            Ok(stream) => stream,
+                            Err(err) => {
+                                println!("{style_bold}{color_green}[Unix]:{style_reset} Error Accepting Connection: {err}");
+                                continue;
+                            }
+                        };
+                        connection += 1;
+                        let queue = queue.clone();
+                        scope.spawn(move || {
+                            serve_stream(Box::new(stream), Peer::Unix(connection), transport, queue, stop);
+                            state.forget_client(Peer::Unix(connection));
+                        });
+                    }
+                });
+            }
+            // the listeners hold their own handles to the queue
+            drop(queue);
+        });
+    }
+}
+
+/* A server servicing requests on a thread of its own, as started by Server::start
+   it is shut down when the handle is dropped, if that hasn't been done already */
+pub struct ServerHandle {
+    udp_addr: Option<SocketAddr>,
+    tcp_addr: Option<SocketAddr>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    stop: Arc<StopSignal>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl ServerHandle {
+    // the address clients reach the server on, which is the UDP address unless UDP is disabled
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        return self.udp_addr.or(self.tcp_addr);
+    }
+
+    // the address datagrams are received on
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        return self.udp_addr;
+    }
+
+    // the address TCP connections are accepted on
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        return self.tcp_addr;
+    }
+
+    // stops servicing requests and waits for every thread of the server to finish
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+        self.stop.stop();
+        // the listeners are blocked waiting for clients, so they are woken up by the server reaching itself
+        if let Some(addr) = self.udp_addr {
+            let local: SocketAddr = if addr.is_ipv4() { (Ipv4Addr::LOCALHOST, 0).into() } else { (Ipv6Addr::LOCALHOST, 0).into() };
+            let _ = UdpSocket::bind(local).and_then(|socket| socket.send_to(&[], ServerHandle::reachable(addr)));
+        }
+        if let Some(addr) = self.tcp_addr {
+            let _ = TcpStream::connect(ServerHandle::reachable(addr));
+        }
+        #[cfg(unix)]
+        if let Some(socket_path) = &self.unix_socket {
+            let _ = UnixStream::connect(socket_path);
+        }
+        let _ = thread.join();
+    }
+
+    // an address the server is bound to, with an address bound on every interface replaced by loopback
+    fn reachable(addr: SocketAddr) -> SocketAddr {
+        if !addr.ip().is_unspecified() {
+            return addr;
+        }
+        let ip: IpAddr = if addr.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() };
+        return SocketAddr::new(ip, addr.port());
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::get_time;
+use crate::protocol::EntryType;
+
+// numbers the temporary files, so that a temporary file left behind by a failed write is never reused by the next one
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/* writes the content of a file in a single step, by writing it to a temporary file in the same directory and moving that into place
+   a crash at any point leaves either the old or the new version of the file, never a mix of both.
+   If replace is not set, this fails when the file already exists */
+fn write_atomically(path: &Path, content: &[u8], replace: bool) -> Result<(), Error> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = dir.join(format!(".{name}.{}.{}.tmp", process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+    let write = || -> Result<(), Error> {
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(content)?;
+        // the new version keeps the permissions of the one it replaces
+        if let Ok(metadata) = fs::metadata(path) {
+            tmp.set_permissions(metadata.permissions())?;
+        }
+        tmp.sync_all()?;
+        if replace {
+            fs::rename(&tmp_path, path)?;
+        } else {
+            // unlike a rename, linking fails if something is already there
+            fs::hard_link(&tmp_path, path)?;
+            fs::remove_file(&tmp_path)?;
+        }
+        // the directory is synced as well so that the new entry itself survives a crash
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        return Ok(());
+    };
+
+    let result = write();
+    if result.is_err() {
+        // don't leave the temporary file behind
+        let _ = fs::remove_file(&tmp_path);
+    }
+    return result;
+}
+
+// Reasons why a path sent by a client could not be resolved
+pub enum PathError {
+    OutsideRoot, // the path points to somewhere outside the server file directory
+    Unresolvable(Error) // the path could not be canonicalized, usually because it doesn't exist
+}
+
+// Normalizes a path sent by a client relative to the root, so that it can never climb above the root with '..' or be absolute
+fn normalize_path(file_path: &str) -> Result<PathBuf, PathError> {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(file_path).components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {},
+            Component::ParentDir => {
+                // popping an empty path means the client is trying to go above the root
+                if !normalized.pop() {
+                    return Err(PathError::OutsideRoot);
+                }
+            },
+            Component::RootDir | Component::Prefix(_) => return Err(PathError::OutsideRoot),
+        }
+    }
+    return Ok(normalized);
+}
+
+/* Resolves a path sent by a client against the (canonical) server file directory.
+   The path is normalized first and then canonicalized so that symlinks pointing outside the root are caught as well.
+   If allow_missing is set, the last component may not exist yet (for creating files) but its directory must */
+fn resolve_path(root: &Path, file_path: &str, allow_missing: bool) -> Result<PathBuf, PathError> {
+    let normalized = normalize_path(file_path)?;
+    let joined = root.join(&normalized);
+    let canonical = match joined.canonicalize() {
+        Ok(canonical) => canonical,
+        // a dangling symlink has metadata of its own and is never treated as missing, since creating it would follow the link
+        Err(err) if allow_missing && err.kind() == ErrorKind::NotFound && joined.symlink_metadata().is_err() => {
+            let name = match normalized.file_name() {
+                Some(name) => name.to_owned(),
+                None => return Err(PathError::Unresolvable(err)),
+            };
+            let parent = joined.parent().unwrap_or(root).canonicalize().map_err(PathError::Unresolvable)?;
+            parent.join(name)
+        },
+        Err(err) => return Err(PathError::Unresolvable(err)),
+    };
+    if !canonical.starts_with(root) {
+        return Err(PathError::OutsideRoot);
+    }
+    return Ok(canonical);
+}
+
+// What a storage knows about a file or directory
+pub struct EntryInfo {
+    pub entry_type: u8, // one of the EntryType constants
+    pub size: u64,
+    pub modified: u64, // epoch milliseconds
+    pub permissions: u32 // unix permission bits
+}
+
+// checks that a range lies within some content, returning it as a range of indices
+fn content_range(content: &[u8], offset: u64, len: u64) -> Result<Range<usize>, Error> {
+    if offset + len > content.len() as u64 {
+        return Err(Error::new(ErrorKind::InvalidInput, "range extends beyond the end of the file"));
+    }
+    return Ok(offset as usize..(offset + len) as usize);
+}
+
+/* A Storage keeps the files that clients operate on. Paths sent by clients are resolved by the storage first,
+   and only paths it has resolved are given back to it.
+   Changes are made to the whole content of a file in memory, which then replaces the file in a single step */
+pub trait Storage: Send + Sync {
+    // resolves a path sent by a client, the last component may not exist yet if allow_missing is set
+    fn resolve(&self, file_path: &str, allow_missing: bool) -> Result<PathBuf, PathError>;
+
+    // the type, size, modification time and permissions of a file or directory
+    fn stat(&self, path: &Path) -> Result<EntryInfo, Error>;
+
+    // the name, resolved path and info of every entry in a directory
+    fn list(&self, path: &Path) -> Result<Vec<(String, PathBuf, EntryInfo)>, Error>;
+
+    // reads exactly amount bytes starting from the offset
+    fn read(&self, path: &Path, offset: u64, amount: u64) -> Result<Vec<u8>, Error>;
+
+    // replaces the content of a file, creating it if it doesn't exist. If replace is not set, this fails when the file already exists
+    fn write(&self, path: &Path, content: &[u8], replace: bool) -> Result<(), Error>;
+
+    // removes a file, directories are never removed
+    fn remove(&self, path: &Path) -> Result<(), Error>;
+
+    // reads a file in its entirety
+    fn read_all(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let size = self.stat(path)?.size;
+        return self.read(path, 0, size);
+    }
+
+    // puts data in at the offset, shifting everything after it to the right
+    fn insert(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let mut content = self.read_all(path)?;
+        let range = content_range(&content, offset, 0)?;
+        content.splice(range, data.iter().copied());
+        return self.write(path, &content, true);
+    }
+
+    // overwrites the content starting from the offset with data
+    fn update(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let mut content = self.read_all(path)?;
+        let range = content_range(&content, offset, data.len() as u64)?;
+        content[range].copy_from_slice(data);
+        return self.write(path, &content, true);
+    }
+
+    // takes out amount bytes starting from the offset, shifting everything after them to the left
+    fn delete(&self, path: &Path, offset: u64, amount: u64) -> Result<(), Error> {
+        let mut content = self.read_all(path)?;
+        let range = content_range(&content, offset, amount)?;
+        content.drain(range);
+        return self.write(path, &content, true);
+    }
+
+    /* puts data at the end of a file, returning the offset it ended up at
+       the whole file goes through write, so a crash never leaves it partly appended */
+    fn append(&self, path: &Path, data: &[u8]) -> Result<u64, Error> {
+        let mut content = self.read_all(path)?;
+        let offset = content.len() as u64;
+        content.extend_from_slice(data);
+        self.write(path, &content, true)?;
+        return Ok(offset);
+    }
+}
+
+// FsStorage serves the files of a directory on disk
+pub struct FsStorage {
+    root: PathBuf // the canonical server file directory
+}
+
+impl FsStorage {
+    // serves the files of a directory, which must exist. client paths are confined to its canonical form
+    pub fn new(dir: &Path) -> Result<FsStorage, Error> {
+        if !dir.is_dir() {
+            return Err(Error::new(ErrorKind::NotFound, "Couldn't find server file directory!"));
+        }
+        let root = dir.canonicalize().map_err(|err| Error::new(err.kind(), format!("Couldn't resolve server file directory: {err}")))?;
+        return Ok(FsStorage{root});
+    }
+
+    // the canonical server file directory
+    pub fn root(&self) -> &Path {
+        return &self.root;
+    }
+
+    fn entry_info(metadata: &fs::Metadata) -> EntryInfo {
+        let entry_type = if metadata.is_dir() { EntryType::DIRECTORY } else { EntryType::FILE };
+        let modified = metadata.modified().map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64).unwrap_or(0);
+        // unix permission bits are sent as is, other platforms only know whether the file is read-only
+        #[cfg(unix)]
+        let permissions: u32 = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o7777;
+        #[cfg(not(unix))]
+        let permissions: u32 = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+        return EntryInfo{entry_type, size: metadata.len(), modified, permissions};
+    }
+}
+
+impl Storage for FsStorage {
+    fn resolve(&self, file_path: &str, allow_missing: bool) -> Result<PathBuf, PathError> {
+        return resolve_path(&self.root, file_path, allow_missing);
+    }
+
+    fn stat(&self, path: &Path) -> Result<EntryInfo, Error> {
+        return Ok(FsStorage::entry_info(&path.metadata()?));
+    }
+
+    // entries that resolve outside the server file directory (through symlinks) are left out
+    fn list(&self, path: &Path) -> Result<Vec<(String, PathBuf, EntryInfo)>, Error> {
+        if path.is_file() {
+            return Err(Error::new(ErrorKind::NotFound, "not a directory"));
+        }
+        let mut entries: Vec<(String, PathBuf, EntryInfo)> = Vec::new();
+        for entry in fs::read_dir(path)?.flatten() {
+            let canonical = match entry.path().canonicalize() {
+                Ok(canonical) if canonical.starts_with(&self.root) => canonical,
+                _ => continue,
+            };
+            let metadata = match canonical.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            entries.push((name, canonical, FsStorage::entry_info(&metadata)));
+        }
+        return Ok(entries);
+    }
+
+    fn read(&self, path: &Path, offset: u64, amount: u64) -> Result<Vec<u8>, Error> {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0u8; amount as usize];
+        // seek to the offset and read the exact size of the buffer which is set to the given amount
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        return Ok(buf);
+    }
+
+    fn read_all(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        return fs::read(path);
+    }
+
+    fn write(&self, path: &Path, content: &[u8], replace: bool) -> Result<(), Error> {
+        return write_atomically(path, content, replace);
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Error> {
+        return fs::remove_file(path);
+    }
+}
+
+// A file kept by the MemoryStorage
+struct MemoryFile {
+    content: Vec<u8>,
+    modified: u64
+}
+
+/* MemoryStorage keeps every file in memory, so nothing is persisted once the server exits
+   paths are relative to an empty root, and a directory exists as long as some file is inside it.
+   Like on disk, files can only be created in a directory that already exists */
+pub struct MemoryStorage {
+    files: RwLock<BTreeMap<PathBuf, MemoryFile>>
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        return MemoryStorage{files: RwLock::new(BTreeMap::new())};
+    }
+
+    // the root always exists, any other directory only through the files inside it
+    fn is_directory(files: &BTreeMap<PathBuf, MemoryFile>, path: &Path) -> bool {
+        return path.as_os_str().is_empty() || files.keys().any(|file| file != path && file.starts_with(path));
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> MemoryStorage {
+        return MemoryStorage::new();
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn resolve(&self, file_path: &str, allow_missing: bool) -> Result<PathBuf, PathError> {
+        let normalized = normalize_path(file_path)?;
+        let files = self.files.read().unwrap();
+        if files.contains_key(&normalized) || MemoryStorage::is_directory(&files, &normalized) {
+            return Ok(normalized);
+        }
+        // a missing path can only be created in a directory that exists, which the root always does
+        let parent = normalized.parent();
+        if !allow_missing || parent.is_none_or(|parent| !MemoryStorage::is_directory(&files, parent)) {
+            return Err(PathError::Unresolvable(Error::new(ErrorKind::NotFound, "no such file or directory")));
+        }
+        return Ok(normalized);
+    }
+
+    fn stat(&self, path: &Path) -> Result<EntryInfo, Error> {
+        let files = self.files.read().unwrap();
+        if let Some(file) = files.get(path) {
+            return Ok(EntryInfo{entry_type: EntryType::FILE, size: file.content.len() as u64, modified: file.modified, permissions: 0o644});
+        }
+        if MemoryStorage::is_directory(&files, path) {
+            return Ok(EntryInfo{entry_type: EntryType::DIRECTORY, size: 0, modified: 0, permissions: 0o755});
+        }
+        return Err(Error::new(ErrorKind::NotFound, "no such file or directory"));
+    }
+
+    fn list(&self, path: &Path) -> Result<Vec<(String, PathBuf, EntryInfo)>, Error> {
+        let files = self.files.read().unwrap();
+        if !MemoryStorage::is_directory(&files, path) {
+            return Err(Error::new(ErrorKind::NotFound, "not a directory"));
+        }
+        // files deeper down only show up as the directory they are in, which is listed once
+        let mut entries: BTreeMap<String, (PathBuf, EntryInfo)> = BTreeMap::new();
+        for (file_path, file) in files.iter() {
+            let relative = match file_path.strip_prefix(path) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative,
+                _ => continue,
+            };
+            let mut components = relative.components();
+            let name = components.next().unwrap().as_os_str().to_string_lossy().into_owned();
+            let entry_path = path.join(&name);
+            let info = if components.next().is_some() {
+                EntryInfo{entry_type: EntryType::DIRECTORY, size: 0, modified: 0, permissions: 0o755}
+            } else {
+                EntryInfo{entry_type: EntryType::FILE, size: file.content.len() as u64, modified: file.modified, permissions: 0o644}
+            };
+            entries.insert(name, (entry_path, info));
+        }
+        return Ok(entries.into_iter().map(|(name, (entry_path, info))| (name, entry_path, info)).collect());
+    }
+
+    fn read(&self, path: &Path, offset: u64, amount: u64) -> Result<Vec<u8>, Error> {
+        let files = self.files.read().unwrap();
+        let file = files.get(path).ok_or_else(|| Error::new(ErrorKind::NotFound, "no such file"))?;
+        let range = content_range(&file.content, offset, amount)?;
+        return Ok(file.content[range].to_vec());
+    }
+
+    fn write(&self, path: &Path, content: &[u8], replace: bool) -> Result<(), Error> {
+        let mut files = self.files.write().unwrap();
+        if MemoryStorage::is_directory(&files, path) || (!replace && files.contains_key(path)) {
+            return Err(Error::new(ErrorKind::AlreadyExists, "file already exists"));
+        }
+        // a file can only be created in a directory that already exists
+        if !files.contains_key(path) && path.parent().is_none_or(|parent| !MemoryStorage::is_directory(&files, parent)) {
+            return Err(Error::new(ErrorKind::NotFound, "no such directory"));
+        }
+        files.insert(path.to_path_buf(), MemoryFile{content: content.to_vec(), modified: get_time() as u64});
+        return Ok(());
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Error> {
+        let mut files = self.files.write().unwrap();
+        if files.remove(path).is_none() {
+            return Err(Error::new(ErrorKind::NotFound, "no such file"));
+        }
+        return Ok(());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn normalize_path_stays_below_the_root() {
+        assert_eq!(normalize_path("a/./b/../c").ok(), Some(PathBuf::from("a/c")));
+        assert_eq!(normalize_path("a/..").ok(), Some(PathBuf::new()));
+        assert!(matches!(normalize_path(".."), Err(PathError::OutsideRoot)));
+        assert!(matches!(normalize_path("a/../../b"), Err(PathError::OutsideRoot)));
+        assert!(matches!(normalize_path("/etc/passwd"), Err(PathError::OutsideRoot)));
+    }
+
+    #[test]
+    fn resolve_path_confines_parent_directories() {
+        let dir = TempDir::new("resolve");
+        fs::create_dir(dir.0.join("sub")).unwrap();
+        fs::write(dir.0.join("sub/file"), b"x").unwrap();
+        assert_eq!(resolve_path(&dir.0, "sub/../sub/file", false).ok(), Some(dir.0.join("sub/file")));
+        assert!(matches!(resolve_path(&dir.0, "sub/../../file", false), Err(PathError::OutsideRoot)));
+        assert!(matches!(resolve_path(&dir.0, "missing", false), Err(PathError::Unresolvable(_))));
+        assert_eq!(resolve_path(&dir.0, "sub/new", true).ok(), Some(dir.0.join("sub/new")));
+        assert!(matches!(resolve_path(&dir.0, "missing/new", true), Err(PathError::Unresolvable(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_path_confines_symlinks() {
+        let dir = TempDir::new("symlinks");
+        let outside = TempDir::new("symlinks-outside");
+        fs::write(outside.0.join("secret"), b"x").unwrap();
+        fs::write(dir.0.join("file"), b"x").unwrap();
+        std::os::unix::fs::symlink(outside.0.join("secret"), dir.0.join("escape")).unwrap();
+        std::os::unix::fs::symlink(&outside.0, dir.0.join("escape_dir")).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("file"), dir.0.join("inside")).unwrap();
+        std::os::unix::fs::symlink(outside.0.join("missing"), dir.0.join("dangling")).unwrap();
+        assert!(matches!(resolve_path(&dir.0, "escape", false), Err(PathError::OutsideRoot)));
+        assert!(matches!(resolve_path(&dir.0, "escape_dir/secret", false), Err(PathError::OutsideRoot)));
+        assert!(matches!(resolve_path(&dir.0, "escape_dir/new", true), Err(PathError::OutsideRoot)));
+        assert_eq!(resolve_path(&dir.0, "inside", false).ok(), Some(dir.0.join("file")));
+        // creating through a dangling symlink would create its target outside the root
+        assert!(matches!(resolve_path(&dir.0, "dangling", true), Err(PathError::Unresolvable(_))));
+    }
+
+    #[test]
+    fn hidden_files_are_served_and_left_alone() {
+        let dir = TempDir::new("hidden");
+        fs::create_dir(dir.0.join(".staging")).unwrap();
+        fs::write(dir.0.join(".staging").join("1.0.tmp"), b"kept").unwrap();
+        let storage = FsStorage::new(&dir.0).unwrap();
+        let path = storage.resolve(".staging/1.0.tmp", false).ok().unwrap();
+        assert_eq!(storage.read(&path, 0, 4).unwrap(), b"kept");
+        assert_eq!(storage.list(storage.root()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn write_replaces_atomically_or_fails_if_exists() {
+        let dir = TempDir::new("write");
+        let storage = FsStorage::new(&dir.0).unwrap();
+        let path = storage.resolve("file", true).ok().unwrap();
+        storage.write(&path, b"first", false).unwrap();
+        assert_eq!(storage.write(&path, b"second", false).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        storage.write(&path, b"second", true).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // no temporary file is left next to it, whether the write succeeded or not
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn append_returns_the_offset_it_wrote_at() {
+        let dir = TempDir::new("append");
+        let storage = FsStorage::new(&dir.0).unwrap();
+        let path = storage.resolve("file", true).ok().unwrap();
+        storage.write(&path, b"abc", false).unwrap();
+        assert_eq!(storage.append(&path, b"de").unwrap(), 3);
+        assert_eq!(storage.read_all(&path).unwrap(), b"abcde");
+        assert!(storage.append(&dir.0.join("missing"), b"x").is_err());
+    }
+
+    // both backends only create files in directories that exist, and tell files and directories apart the same way
+    fn check_parent_directories(storage: &dyn Storage) {
+        assert!(matches!(storage.resolve("missing/file", true), Err(PathError::Unresolvable(_))));
+        let file = storage.resolve("file", true).ok().unwrap();
+        storage.write(&file, b"x", false).unwrap();
+        assert!(matches!(storage.resolve("file/nested", true), Err(PathError::Unresolvable(_))));
+        assert!(storage.write(&file.join("nested"), b"x", false).is_err());
+        assert!(storage.list(&file).is_err());
+        let root = storage.resolve("", false).ok().unwrap();
+        assert_eq!(storage.stat(&root).unwrap().entry_type, EntryType::DIRECTORY);
+        let entries = storage.list(&root).unwrap();
+        assert_eq!(entries.iter().map(|(name, _, info)| (name.as_str(), info.entry_type)).collect::<Vec<_>>(), vec![("file", EntryType::FILE)]);
+    }
+
+    #[test]
+    fn files_are_only_created_in_existing_directories() {
+        let dir = TempDir::new("parents");
+        check_parent_directories(&FsStorage::new(&dir.0).unwrap());
+        check_parent_directories(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn changes_shift_content_within_range() {
+        let storage = MemoryStorage::new();
+        let path = storage.resolve("file", true).ok().unwrap();
+        storage.write(&path, b"hello", false).unwrap();
+        storage.insert(&path, 5, b" world").unwrap();
+        storage.update(&path, 0, b"J").unwrap();
+        storage.delete(&path, 1, 4).unwrap();
+        assert_eq!(storage.read_all(&path).unwrap(), b"J world");
+        assert_eq!(storage.insert(&path, 8, b"x").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(storage.update(&path, 6, b"xy").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,23 @@
+/* Fixtures shared by the unit tests of the server modules */
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// a fresh directory under the system temporary directory, removed when dropped
+pub(crate) struct TempDir(pub(crate) PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("server-test-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return TempDir(dir.canonicalize().unwrap());
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::str;
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use inline_colorization::*;
+
+use crate::fault::{DatagramKey, DelayQueue, FaultInjector, FaultPolicy};
+use crate::get_time;
+use crate::protocol::{Fragment, ResponseMarshal};
+use crate::server::StopSignal;
+
+/* Peer identifies a client along with the transport it reached the server with
+   clients on a unix socket usually have no address of their own, so they are told apart by the number of their connection */
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum Peer {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Unix(u64)
+}
+
+impl Peer {
+    // the name of the transport, as used for logging
+    pub fn transport_name(&self) -> &'static str {
+        match self {
+            Peer::Udp(_) => "UDP",
+            Peer::Tcp(_) => "TCP",
+            Peer::Unix(_) => "Unix",
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Udp(addr) => write!(f, "udp://{addr}"),
+            Peer::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Peer::Unix(connection) => write!(f, "unix://{connection}"),
+        }
+    }
+}
+
+// parses what Display produces, a plain socket address is taken to be a UDP client as that was the only transport before
+impl str::FromStr for Peer {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Peer, ()> {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            return addr.parse().map(Peer::Udp).map_err(|_| ());
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            return addr.parse().map(Peer::Tcp).map_err(|_| ());
+        } else if let Some(connection) = s.strip_prefix("unix://") {
+            return connection.parse().map(Peer::Unix).map_err(|_| ());
+        }
+        return s.parse().map(Peer::Udp).map_err(|_| ());
+    }
+}
+
+// A connection that requests are read from and responses are written to, each as a frame prefixed by its length
+pub trait Stream: Read + Write + Send {
+    // a second handle to the same connection, so that responses can be written while the next request is being read
+    fn try_clone_boxed(&self) -> Result<Box<dyn Stream>, Error>;
+
+    // how long a write may block before it fails
+    fn set_write_timeout(&self, timeout: Duration) -> Result<(), Error>;
+
+    // shuts the connection down in both directions, which wakes up anything blocked reading from or writing to it
+    fn close(&self) -> Result<(), Error>;
+}
+
+impl Stream for TcpStream {
+    fn try_clone_boxed(&self) -> Result<Box<dyn Stream>, Error> {
+        return Ok(Box::new(self.try_clone()?));
+    }
+
+    fn set_write_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        return TcpStream::set_write_timeout(self, Some(timeout));
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        return self.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn try_clone_boxed(&self) -> Result<Box<dyn Stream>, Error> {
+        return Ok(Box::new(self.try_clone()?));
+    }
+
+    fn set_write_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        return UnixStream::set_write_timeout(self, Some(timeout));
+    }
+
+    fn close(&self) -> Result<(), Error> {
+        return self.shutdown(Shutdown::Both);
+    }
+}
+
+/* where the frames for a connection are queued up for its writer thread, so that no worker ever blocks on a slow client
+   a client that lets too many frames queue up isn't reading them, and is disconnected */
+pub(crate) struct StreamWriter {
+    frames: mpsc::SyncSender<Vec<u8>>,
+    connection: Box<dyn Stream> // a handle to close the connection with
+}
+
+// The fragments of a response that was too large for a single datagram
+pub(crate) struct FragmentedMessage {
+    fragments: Vec<Vec<u8>>, // each fragment is a complete datagram, header included
+    key: DatagramKey, // the key of the whole message, which each fragment's is derived from
+    sent_at: u128
+}
+
+// FragmentManager hands out message ids and keeps recently fragmented messages for retransmission
+pub(crate) struct FragmentManager {
+    next_id: u32,
+    history: HashMap<(SocketAddr, u32), FragmentedMessage> // map destination client and message id to the fragments sent to it
+}
+
+impl FragmentManager {
+    // how long fragments are kept around for clients to ask for them again
+    const RETENTION: u128 = 30000;
+
+    // forget about messages that were sent too long ago
+    fn clear_expired(&mut self) {
+        let time = get_time();
+        self.history.retain(|_, message| time <= message.sent_at + FragmentManager::RETENTION);
+    }
+}
+
+/* all packets are sent through the Transport, over whichever transport the client reached the server with
+   over UDP, responses that don't fit in a single datagram are split into fragments, each of which has a 13 byte header.
+   Over the stream transports (TCP and unix sockets), every request and response is a frame prefixed by its length as a u32.
+   Any faults being injected are applied to datagrams here as well */
+pub struct Transport<'a> {
+    udp: Option<&'a UdpSocket>,
+    fragment_size: usize, // the largest datagram that will be sent, header included
+    fragment_manager: Mutex<FragmentManager>,
+    streams: Mutex<HashMap<Peer, StreamWriter>>, // where responses are queued for every open connection
+    pub(crate) faults: FaultInjector,
+    delayed: DelayQueue // datagrams held back by a delay fault
+}
+
+impl<'a> Transport<'a> {
+    // the largest request accepted over a stream, anything larger closes the connection
+    const MAX_FRAME_SIZE: usize = 16777216;
+    // the most responses that may be waiting to be written to a connection
+    const MAX_QUEUED_FRAMES: usize = 256;
+    // how long writing a response to a connection may take before the client is given up on
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    // a transport that sends datagrams from the given socket, if the server is using UDP at all
+    pub fn new(udp: Option<&'a UdpSocket>, fragment_size: usize, faults: FaultPolicy) -> Transport<'a> {
+        return Transport{
+            udp,
+            fragment_size,
+            fragment_manager: Mutex::new(FragmentManager{next_id: 0, history: HashMap::new()}),
+            streams: Mutex::new(HashMap::new()),
+            faults: FaultInjector::new(faults),
+            delayed: DelayQueue::new()
+        };
+    }
+
+    // sends a single datagram, unless a fault gets in the way
+    fn send_datagram(&self, buf: &[u8], addr: SocketAddr, key: DatagramKey) {
+        if self.udp.is_none() {
+            println!("{style_bold}{color_green}[UDP]:{style_reset} Can't send to {addr}, UDP is disabled");
+            return;
+        }
+        let len = buf.len();
+        let copies = self.faults.reply_copies(addr, key);
+        if copies.is_empty() {
+            println!("{style_bold}{color_red}[Fault]:{style_reset} Dropping {len} bytes to {addr}");
+            return;
+        } else if copies.len() > 1 {
+            println!("{style_bold}{color_red}[Fault]:{style_reset} Duplicating {len} bytes to {addr}");
+        }
+        for delay in copies {
+            match delay {
+                Some(delay) => {
+                    let ms = delay.as_millis();
+                    println!("{style_bold}{color_red}[Fault]:{style_reset} Delaying {len} bytes to {addr} by {ms}ms");
+                    self.delayed.push(delay, buf.to_vec(), addr);
+                },
+                None => self.write_datagram(buf, addr),
+            }
+        }
+    }
+
+    // sends the datagrams held back by delay faults as they become due, until the transport is closed
+    pub fn deliver_delayed(&self) {
+        while let Some((buf, addr)) = self.delayed.pop_due() {
+            self.write_datagram(&buf, addr);
+        }
+    }
+
+    // writes a single datagram to the socket
+    fn write_datagram(&self, buf: &[u8], addr: SocketAddr) {
+        let socket = match self.udp {
+            Some(socket) => socket,
+            None => return,
+        };
+        let result = socket.send_to(buf, addr);
+        if result.is_ok() {
+            let amt = result.unwrap();
+            println!("{style_bold}{color_green}[UDP]:{style_reset} Sent {amt} bytes to {addr}");
+        } else {
+            let err: Error = result.unwrap_err();
+            println!("{style_bold}{color_green}[UDP]:{style_reset} Error Sending Data: {err}");
+        }
+    }
+
+    // queues a single frame up for the writer of a connection, without waiting for it to be written
+    fn send_frame(&self, buf: &[u8], peer: Peer) {
+        let name = peer.transport_name();
+        let mut streams = self.streams.lock().unwrap();
+        let writer = match streams.get(&peer) {
+            Some(writer) => writer,
+            None => {
+                println!("{style_bold}{color_green}[{name}]:{style_reset} Can't send to {peer}, the connection has been closed");
+                return;
+            }
+        };
+        let mut frame: Vec<u8> = Vec::new();
+        frame.extend((buf.len() as u32).to_be_bytes());
+        frame.extend(buf);
+        match writer.frames.try_send(frame) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => {
+                println!("{style_bold}{color_green}[{name}]:{style_reset} {peer} isn't reading its responses, closing the connection");
+                let _ = writer.connection.close();
+                streams.remove(&peer);
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                println!("{style_bold}{color_green}[{name}]:{style_reset} Can't send to {peer}, the connection has been closed");
+            },
+        }
+    }
+
+    /* sends a response to a client, splitting it into fragments if it is too large for a datagram
+       the key tells which datagram it is when deciding on the faults injected into it */
+    pub fn send(&self, response: &ResponseMarshal, peer: Peer, key: DatagramKey) {
+        let buf = response.to_bytes();
+        let addr = match peer {
+            Peer::Udp(addr) => addr,
+            // streams have no size limit of their own
+            _ => return self.send_frame(&buf, peer),
+        };
+        if buf.len() <= self.fragment_size {
+            self.send_datagram(&buf, addr, key);
+            return;
+        }
+
+        let mut fragment_manager = self.fragment_manager.lock().unwrap();
+        fragment_manager.clear_expired();
+        let id = fragment_manager.next_id;
+        fragment_manager.next_id = fragment_manager.next_id.wrapping_add(1);
+
+        let chunks: Vec<&[u8]> = buf.chunks(self.fragment_size - Fragment::HEADER_SIZE).collect();
+        let total = chunks.len() as u32;
+        let len = buf.len();
+        println!("{style_bold}{color_green}[UDP]:{style_reset} Splitting {len} bytes into {total} fragments with message id {id}");
+        let mut fragments: Vec<Vec<u8>> = Vec::new();
+        for (seq, chunk) in chunks.iter().enumerate() {
+            let fragment = Fragment{id, seq: seq as u32, total, chunk}.to_bytes();
+            self.send_datagram(&fragment, addr, key.fragment(seq as u32));
+            fragments.push(fragment);
+        }
+        fragment_manager.history.insert((addr, id), FragmentedMessage{fragments, key, sent_at: get_time()});
+    }
+
+    // sends the requested fragments of a message again, returning false if the message is no longer kept
+    pub fn resend(&self, peer: Peer, id: u32, seqs: &[u32]) -> bool {
+        // only datagrams are ever fragmented
+        let addr = match peer {
+            Peer::Udp(addr) => addr,
+            _ => return false,
+        };
+        let mut fragment_manager = self.fragment_manager.lock().unwrap();
+        fragment_manager.clear_expired();
+        let message = match fragment_manager.history.get(&(addr, id)) {
+            Some(message) => message,
+            None => return false,
+        };
+        for seq in seqs {
+            // sequence numbers that don't exist are simply ignored
+            if let Some(fragment) = message.fragments.get(*seq as usize) {
+                println!("{style_bold}{color_green}[UDP]:{style_reset} Resending fragment {seq} of message {id}");
+                self.send_datagram(fragment, addr, message.key.fragment(*seq));
+            }
+        }
+        return true;
+    }
+
+    // keeps the queue of a new connection so that responses can be written to it
+    fn open_stream(&self, peer: Peer, frames: mpsc::SyncSender<Vec<u8>>, connection: Box<dyn Stream>) {
+        self.streams.lock().unwrap().insert(peer, StreamWriter{frames, connection});
+    }
+
+    // forgets a connection that has been closed, which lets its writer finish once the frames already queued are written
+    fn close_stream(&self, peer: Peer) {
+        self.streams.lock().unwrap().remove(&peer);
+    }
+
+    // closes every connection and stops delivering delayed datagrams, for when the server stops
+    pub(crate) fn close(&self) {
+        for writer in self.streams.lock().unwrap().values() {
+            let _ = writer.connection.close();
+        }
+        self.delayed.close();
+    }
+}
+
+// writes the frames queued for a connection in order until it is closed, closing it early if a write fails or times out
+fn write_frames(mut stream: Box<dyn Stream>, peer: Peer, frames: mpsc::Receiver<Vec<u8>>) {
+    let name = peer.transport_name();
+    for frame in frames.iter() {
+        let result = stream.write_all(&frame);
+        if result.is_ok() {
+            let amt = frame.len() - 4;
+            println!("{style_bold}{color_green}[{name}]:{style_reset} Sent {amt} bytes to {peer}");
+        } else {
+            let err: Error = result.unwrap_err();
+            println!("{style_bold}{color_green}[{name}]:{style_reset} Error Sending Data: {err}");
+            let _ = stream.close();
+            return;
+        }
+    }
+}
+
+// keeps listening for datagrams on the UDP socket until the server stops, queueing them up for the workers
+pub(crate) fn listen_udp(socket: &UdpSocket, transport: &Transport, queue: mpsc::Sender<(Vec<u8>, Peer)>, stop: &StopSignal) {
+    loop { // while(True)
+        // a datagram can't be larger than this
+        let mut buf: [u8; 65536] = [0; 65536];
+        let result: Result<(usize, SocketAddr), Error> = socket.recv_from(&mut buf);
+        // the server wakes this up with a datagram of its own when it stops
+        if stop.is_stopped() {
+            return;
+        }
+        if result.is_ok() {
+            // if no errors reading the data, hand the request over to the workers
+            let (amt, src) = result.unwrap();
+            println!("{style_bold}\n{color_green}[UDP]:{style_reset} Received {amt} bytes from {src}");
+            if transport.faults.drop_request(src, &buf[..amt]) {
+                println!("{style_bold}{color_red}[Fault]:{style_reset} Dropping {amt} bytes from {src}");
+                continue;
+            }
+            // the handler only gets the bytes that were received, so it can never read past them
+            if queue.send((buf[..amt].to_vec(), Peer::Udp(src))).is_err() {
+                return;
+            }
+        } else {
+            let err: Error = result.unwrap_err();
+            println!("{style_bold}{color_green}[UDP]:{style_reset} Error Receiving Data: {err}");
+        }
+    }
+}
+
+/* reads length-prefixed requests from a connection until it is closed, queueing them up for the workers
+   responses are written by a thread of its own, so a client that doesn't read them only ever holds up itself */
+pub(crate) fn serve_stream(mut stream: Box<dyn Stream>, peer: Peer, transport: &Transport, queue: mpsc::Sender<(Vec<u8>, Peer)>, stop: &StopSignal) {
+    let name = peer.transport_name();
+    let handles = stream.set_write_timeout(Transport::WRITE_TIMEOUT).and_then(|_| Ok((stream.try_clone_boxed()?, stream.try_clone_boxed()?)));
+    let (writer, connection) = match handles {
+        Ok(handles) => handles,
+        Err(err) => {
+            println!("{style_bold}{color_green}[{name}]:{style_reset} Couldn't set up the connection of {peer}: {err}");
+            return;
+        }
+    };
+    let (frames, receiver) = mpsc::sync_channel(Transport::MAX_QUEUED_FRAMES);
+    transport.open_stream(peer, frames, connection);
+    // a connection that comes in as the server stops may have missed being closed along with the others
+    if stop.is_stopped() {
+        transport.close_stream(peer);
+        return;
+    }
+    println!("{style_bold}{color_green}[{name}]:{style_reset} {peer} connected");
+
+    thread::scope(|scope| {
+        scope.spawn(move || write_frames(writer, peer, receiver));
+        read_frames(&mut stream, peer, &queue);
+        // the writer is left to finish the responses already queued, and gives up on them if the client doesn't take them in time
+        transport.close_stream(peer);
+    });
+    println!("{style_bold}{color_green}[{name}]:{style_reset} {peer} disconnected");
+}
+
+// reads length-prefixed requests from a connection until it is closed or sends something invalid
+fn read_frames(stream: &mut Box<dyn Stream>, peer: Peer, queue: &mpsc::Sender<(Vec<u8>, Peer)>) {
+    let name = peer.transport_name();
+    loop {
+        let mut len_buf: [u8; 4] = [0; 4];
+        if stream.read_exact(&mut len_buf).is_err() {
+            break;
+        }
+        let len = u32::from_be_bytes(len_buf) as usize;
+        if len > Transport::MAX_FRAME_SIZE {
+            println!("{style_bold}{color_green}[{name}]:{style_reset} {peer} sent a frame of {len} bytes, which is too large");
+            break;
+        }
+        let mut buf: Vec<u8> = vec![0; len];
+        if stream.read_exact(&mut buf).is_err() {
+            break;
+        }
+        println!("{style_bold}\n{color_green}[{name}]:{style_reset} Received {len} bytes from {peer}");
+        if queue.send((buf, peer)).is_err() {
+            break;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::StatusCode;
+
+    // the sequence numbers and chunks of the fragments that arrive on a socket, until it has been quiet for a while
+    fn receive_fragments(socket: &UdpSocket) -> Vec<(u32, u32, Vec<u8>)> {
+        let mut fragments: Vec<(u32, u32, Vec<u8>)> = Vec::new();
+        let mut buf = [0; 128];
+        while let Ok(amt) = socket.recv(&mut buf) {
+            let fragment = Fragment::from_bytes(&buf[..amt]).unwrap();
+            fragments.push((fragment.seq, fragment.total, fragment.chunk.to_vec()));
+        }
+        return fragments;
+    }
+
+    #[test]
+    fn fragments_are_sent_again_when_asked_for() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let faults = FaultPolicy{drop_requests: 0.0, drop_replies: 0.0, duplicate_replies: 0.0, delay_replies: 0.0, max_delay: Duration::ZERO, seed: 0};
+        let transport = Transport::new(Some(&server), 64, faults);
+        let peer = Peer::Udp(client.local_addr().unwrap());
+        let response = ResponseMarshal{status: StatusCode::GOOD, timestamp: None, data: (0..200).collect()};
+        transport.send(&response, peer, DatagramKey::reply(b"request"));
+
+        // 201 bytes in chunks of 51 are 4 fragments, which put back together are the response
+        let fragments = receive_fragments(&client);
+        assert_eq!(fragments.iter().map(|(seq, total, _)| (*seq, *total)).collect::<Vec<_>>(), vec![(0, 4), (1, 4), (2, 4), (3, 4)]);
+        assert_eq!(fragments.into_iter().flat_map(|(_, _, chunk)| chunk).collect::<Vec<u8>>(), response.to_bytes());
+        // only the fragments asked for are sent again, and those that don't exist are ignored
+        assert!(transport.resend(peer, 0, &[2, 9]));
+        assert_eq!(receive_fragments(&client).iter().map(|(seq, _, _)| *seq).collect::<Vec<_>>(), vec![2]);
+        assert!(!transport.resend(peer, 1, &[0]));
+        assert!(!transport.resend(Peer::Tcp(client.local_addr().unwrap()), 0, &[0]));
+    }
+}
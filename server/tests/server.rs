@@ -0,0 +1,268 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use client::{Client, ClientError, EntryType, MonitorEvent};
+use protocol::{RequestMarshal, RequestOperation, ResponseMarshal, StatusCode};
+use server::{Server, ServerHandle};
+
+// a server keeping its files in memory, on a free local port
+fn start_server(at_most_once: bool) -> ServerHandle {
+    let server = Server::builder()
+        .in_memory()
+        .at_most_once(at_most_once)
+        .udp("127.0.0.1:0")
+        .build()
+        .unwrap();
+    return server.start();
+}
+
+fn connect(server: &ServerHandle) -> Client {
+    return Client::builder()
+        .bind("127.0.0.1:0")
+        .timeout(Duration::from_millis(500))
+        .retries(3)
+        .connect(server.local_addr().unwrap())
+        .unwrap();
+}
+
+#[test]
+fn insert_and_read_back() {
+    let server = start_server(false);
+    let mut client = connect(&server);
+    client.create("notes.txt", b"world", true).unwrap();
+    client.insert("notes.txt", 0, b"hello ").unwrap();
+    assert_eq!(client.read("notes.txt", 0, 11).unwrap().data, b"hello world");
+    assert_eq!(client.append("notes.txt", b"!").unwrap(), 11);
+    assert_eq!(client.read("notes.txt", 6, 6).unwrap().data, b"world!");
+    assert_eq!(client.stat("notes.txt").unwrap().size, 12);
+    client.disconnect().unwrap();
+    server.shutdown();
+}
+
+#[test]
+fn errors_come_back_as_statuses() {
+    let server = start_server(true);
+    let mut client = connect(&server);
+    assert!(matches!(client.read("missing.txt", 0, 1), Err(ClientError::Status{status: 5, ..})));
+    assert!(matches!(client.create("dir/notes.txt", b"", true), Err(ClientError::Status{status: 5, ..})));
+    client.create("notes.txt", b"abc", true).unwrap();
+    assert!(matches!(client.create("notes.txt", b"abc", true), Err(ClientError::Status{status: 10, ..})));
+    assert!(matches!(client.read("notes.txt", 2, 5), Err(ClientError::Status{status: 6, ..})));
+    assert!(matches!(client.read("../notes.txt", 0, 1), Err(ClientError::Status{status: 3, ..})));
+    server.shutdown();
+}
+
+#[test]
+fn validate_only_sends_content_that_changed() {
+    let server = start_server(false);
+    let mut client = connect(&server);
+    client.create("notes.txt", b"abc", true).unwrap();
+    let cached = client.read("notes.txt", 0, 3).unwrap();
+    assert!(client.validate("notes.txt", 0, 3, cached.modified).unwrap().is_none());
+    client.update("notes.txt", 0, b"x").unwrap();
+    // every change moves the modification time forward, however quickly it follows the last one
+    let fresh = client.validate("notes.txt", 0, 3, cached.modified).unwrap().unwrap();
+    assert_eq!(fresh.data, b"xbc");
+    assert!(fresh.modified > cached.modified);
+    assert!(client.validate("notes.txt", 0, 3, fresh.modified).unwrap().is_none());
+    server.shutdown();
+}
+
+#[test]
+fn binary_content_round_trips() {
+    let server = start_server(false);
+    let mut client = connect(&server);
+    // every byte value, which isn't valid UTF-8 as a whole
+    let content: Vec<u8> = (0..=255).collect();
+    client.create("image.bin", &content, true).unwrap();
+    assert_eq!(client.read("image.bin", 0, 256).unwrap().data, content);
+    // reading part of a multi-byte character only returns those bytes
+    client.create("text.txt", "héllo".as_bytes(), true).unwrap();
+    assert_eq!(client.read("text.txt", 0, 2).unwrap().data, b"h\xc3");
+    server.shutdown();
+}
+
+#[test]
+fn malformed_requests_are_answered_instead_of_crashing_the_server() {
+    let server = start_server(false);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    socket.connect(server.local_addr().unwrap()).unwrap();
+    // a read whose path claims to be longer than the datagram, and one that stops partway through the header
+    let mut buf = [0; 64];
+    for request in [&[0, 0, 0, 1, 0, 0, 0, 0, 2, 0, 0, 0, 99, b'a'][..], &[0, 0, 0, 2, 0][..]] {
+        socket.send(request).unwrap();
+        let amt = socket.recv(&mut buf).unwrap();
+        assert_eq!(buf[..amt].first(), Some(&4));
+    }
+    // and it is still there for everyone else
+    let mut client = connect(&server);
+    client.create("notes.txt", b"abc", true).unwrap();
+    server.shutdown();
+}
+
+#[test]
+fn removed_files_are_gone_for_their_monitors() {
+    let server = start_server(false);
+    let mut writer = connect(&server);
+    writer.create("notes.txt", b"abc", true).unwrap();
+    let mut watcher = connect(&server);
+    let watching = thread::spawn(move || {
+        let mut events: Vec<MonitorEvent> = Vec::new();
+        // the monitor ends by itself once the file is removed
+        watcher.monitor("notes.txt", Duration::from_secs(10), |event| {
+            events.push(event);
+            return true;
+        }).unwrap();
+        return events;
+    });
+    // the monitor has to be in place before the file is removed
+    thread::sleep(Duration::from_millis(200));
+    writer.remove("notes.txt").unwrap();
+    assert!(matches!(watching.join().unwrap()[..], [MonitorEvent::Removed]));
+    assert!(matches!(writer.read("notes.txt", 0, 1), Err(ClientError::Status{status: 5, ..})));
+    assert!(matches!(writer.remove("notes.txt"), Err(ClientError::Status{status: 5, ..})));
+    writer.create("notes.txt", b"", true).unwrap();
+    assert_eq!(writer.stat("notes.txt").unwrap().size, 0);
+    server.shutdown();
+}
+
+#[test]
+fn list_and_stat_describe_files_and_directories() {
+    let dir = std::env::temp_dir().join(format!("server-test-list-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("notes.txt"), b"hello").unwrap();
+    let server = Server::builder().dir(&dir).udp("127.0.0.1:0").build().unwrap().start();
+    let mut client = connect(&server);
+    let entries = client.list("").unwrap();
+    assert_eq!(entries.iter().map(|entry| (entry.name.as_str(), entry.entry_type)).collect::<Vec<_>>(), vec![("docs", EntryType::DIRECTORY), ("notes.txt", EntryType::FILE)]);
+    assert_eq!(entries[1].size, 5);
+    assert!(client.list("docs").unwrap().is_empty());
+    let metadata = client.stat("notes.txt").unwrap();
+    assert_eq!((metadata.entry_type, metadata.size), (EntryType::FILE, 5));
+    assert!(metadata.modified > 0);
+    assert_eq!(client.stat("docs").unwrap().entry_type, EntryType::DIRECTORY);
+    assert!(matches!(client.list("notes.txt"), Err(ClientError::Status{status: 15, ..})));
+    assert!(matches!(client.stat("missing.txt"), Err(ClientError::Status{status: 5, ..})));
+    server.shutdown();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_files_can_be_read_and_grown_at_the_end() {
+    let server = start_server(false);
+    let mut client = connect(&server);
+    client.create("log.txt", b"", true).unwrap();
+    assert!(client.read("log.txt", 0, 0).unwrap().data.is_empty());
+    client.insert("log.txt", 0, b"one").unwrap();
+    // inserting right at the end appends, while anything past it is out of range
+    client.insert("log.txt", 3, b" two").unwrap();
+    assert!(matches!(client.insert("log.txt", 8, b"!"), Err(ClientError::Status{status: 6, ..})));
+    assert_eq!(client.append("log.txt", b" three").unwrap(), 7);
+    assert_eq!(client.read("log.txt", 0, 13).unwrap().data, b"one two three");
+    assert!(client.read("log.txt", 13, 0).unwrap().data.is_empty());
+    server.shutdown();
+}
+
+#[test]
+fn large_files_are_sent_in_fragments() {
+    let server = Server::builder().in_memory().udp("127.0.0.1:0").fragment_size(512).build().unwrap().start();
+    let mut client = connect(&server);
+    let content: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+    client.create("large.bin", &content, true).unwrap();
+    assert_eq!(client.read("large.bin", 0, 20000).unwrap().data, content);
+    assert_eq!(client.read("large.bin", 19000, 1000).unwrap().data, &content[19000..]);
+    server.shutdown();
+}
+
+// sends a request as a frame over a stream and waits for the frame of its response
+fn call_stream(stream: &mut (impl Read + Write), request: &RequestMarshal) -> ResponseMarshal {
+    let buf = request.to_bytes();
+    stream.write_all(&(buf.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(buf).unwrap();
+    let mut len_buf = [0; 4];
+    stream.read_exact(&mut len_buf).unwrap();
+    let mut response = vec![0; u32::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut response).unwrap();
+    return ResponseMarshal::from_bytes(&response, request.has_timestamp()).unwrap();
+}
+
+#[test]
+fn stream_transports_serve_the_same_files() {
+    let socket_path = std::env::temp_dir().join(format!("server-test-{}.sock", process::id()));
+    let _ = fs::remove_file(&socket_path);
+    let mut builder = Server::builder().in_memory().udp("127.0.0.1:0").tcp("127.0.0.1:0");
+    if cfg!(unix) {
+        builder = builder.unix_socket(&socket_path);
+    }
+    let server = builder.build().unwrap().start();
+
+    let mut tcp = TcpStream::connect(server.tcp_addr().unwrap()).unwrap();
+    let mut create = RequestMarshal::new(1, 0, RequestOperation::CREATE);
+    create.add_bytes(b"notes.txt");
+    create.add_byte(0);
+    create.add_bytes(b"over tcp");
+    assert_eq!(call_stream(&mut tcp, &create).status, StatusCode::GOOD);
+    let mut read = RequestMarshal::new(2, 0, RequestOperation::READ);
+    read.add_bytes(b"notes.txt");
+    read.add_int(0);
+    read.add_int(8);
+    assert_eq!(call_stream(&mut tcp, &read).data, b"over tcp");
+    #[cfg(unix)]
+    {
+        let mut unix = std::os::unix::net::UnixStream::connect(&socket_path).unwrap();
+        assert_eq!(call_stream(&mut unix, &read).data, b"over tcp");
+    }
+    // and datagram clients see the same files
+    assert_eq!(connect(&server).read("notes.txt", 0, 8).unwrap().data, b"over tcp");
+    server.shutdown();
+    let _ = fs::remove_file(&socket_path);
+}
+
+#[test]
+fn monitor_resyncs_to_follow_deltas() {
+    let server = start_server(false);
+    let mut writer = connect(&server);
+    writer.create("notes.txt", b"world", true).unwrap();
+    let mut watcher = connect(&server);
+    let (events, received) = mpsc::channel();
+    let watching = thread::spawn(move || {
+        // the first delta has no copy to be applied to, so the whole file is asked for again, and the next delta patches that copy
+        return watcher.monitor("notes.txt", Duration::from_secs(10), |event| {
+            let content = match event {
+                MonitorEvent::Changed(content) => content.data,
+                _ => return false,
+            };
+            let done = content == b"hello world!";
+            events.send(content).unwrap();
+            return !done;
+        });
+    });
+    // the monitor has to be in place before anything changes
+    thread::sleep(Duration::from_millis(200));
+    writer.insert("notes.txt", 0, b"hello ").unwrap();
+    writer.append("notes.txt", b"!").unwrap();
+    let mut last: Vec<u8> = Vec::new();
+    while last != b"hello world!" {
+        last = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(last == b"hello world" || last == b"hello world!");
+    }
+    watching.join().unwrap().unwrap();
+    server.shutdown();
+}
+
+#[test]
+fn shut_down_server_stops_responding() {
+    let server = start_server(false);
+    let mut client = connect(&server);
+    client.create("notes.txt", b"abc", true).unwrap();
+    server.shutdown();
+    // nothing is listening anymore, which a connected socket may also be told about
+    assert!(matches!(client.read("notes.txt", 0, 3), Err(ClientError::Timeout | ClientError::Io(_))));
+}